@@ -0,0 +1,104 @@
+//! Compare the element-by-element falkvbo loader against the memory mapped
+//! one
+//!
+//! Run with `cargo bench --bench load [-- <file.falkvbo>]`. Without a
+//! file, a synthetic legacy falkvbo is generated in the temp directory.
+
+#![allow(clippy::print_with_newline)]
+
+use std::fs::File;
+use std::io::{self, Write, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::hint::black_box;
+
+
+/// Number of verticies in the synthetic mesh
+const SYNTHETIC_VERTICIES: u32 = 2_000_000;
+
+/// Number of times each loader is run
+const ITERS: u32 = 5;
+
+/// Write a synthetic legacy falkvbo grid mesh to `path`
+fn write_synthetic(path: &Path) -> io::Result<()> {
+    let mut fd = BufWriter::new(File::create(path)?);
+
+    // Lay the verticies out in a square grid
+    let width = (SYNTHETIC_VERTICIES as f64).sqrt() as u32;
+    let num_verticies = width * width;
+    fd.write_all(&(num_verticies as u64).to_le_bytes())?;
+    for ii in 0..num_verticies {
+        let x = (ii % width) as f32;
+        let z = (ii / width) as f32;
+        let y = (x * 0.1).sin() * (z * 0.1).cos() * 10.;
+        for val in &[x, y, z] {
+            fd.write_all(&val.to_le_bytes())?;
+        }
+    }
+
+    // Two triangles per grid cell
+    let cells = (width - 1) * (width - 1);
+    fd.write_all(&(cells as u64 * 2).to_le_bytes())?;
+    for ii in 0..cells {
+        let x = ii % (width - 1);
+        let z = ii / (width - 1);
+        let a = z * width + x;
+        for tri in &[[a, a + width, a + 1], [a + 1, a + width, a + width + 1]] {
+            for idx in tri {
+                fd.write_all(&idx.to_le_bytes())?;
+            }
+        }
+    }
+
+    fd.flush()
+}
+
+/// Run `func` `ITERS` times and print the average time it took
+fn bench<F: FnMut() -> u64>(name: &str, mut func: F) {
+    // Warm up the page cache
+    black_box(func());
+
+    let start = Instant::now();
+    for _ in 0..ITERS {
+        black_box(func());
+    }
+    print!("{:>8} {:10.3} ms/iter\n", name,
+           start.elapsed().as_secs_f64() * 1000. / ITERS as f64);
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Use a user provided mesh if there is one, otherwise make one up
+    let user_path = std::env::args().skip(1).find(|x| !x.starts_with('-'));
+    let (path, synthetic) = match user_path {
+        Some(path) => (PathBuf::from(path), false),
+        None => {
+            let path = std::env::temp_dir().join(
+                format!("load_falkvbo_bench_{}.falkvbo", std::process::id()));
+            write_synthetic(&path)?;
+            (path, true)
+        }
+    };
+
+    let mesh = falkvbo::map(&path)?;
+    print!("{}: {} verticies, {} triangles\n", path.display(),
+           mesh.vertices().len(), mesh.triangles().len());
+    drop(mesh);
+
+    // Both loaders touch every byte of the data, like the GPU upload would
+    bench("load", || {
+        let (_, mesh) = falkvbo::load(&path).unwrap();
+        mesh.vertices.iter().map(|x| x.0.to_bits() as u64).sum::<u64>() +
+            mesh.triangles.iter().map(|x| x[0] as u64).sum::<u64>()
+    });
+    bench("map", || {
+        let mesh = falkvbo::map(&path).unwrap();
+        mesh.vertices().iter().map(|x| x.0.to_bits() as u64).sum::<u64>() +
+            mesh.triangles().iter().map(|x| x[0] as u64).sum::<u64>()
+    });
+
+    if synthetic {
+        std::fs::remove_file(&path)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,320 @@
+//! The falkvbo mesh format, shared between `mapcombine` which writes it and
+//! the viewer which reads it
+//!
+//! A falkvbo file is a list of unique verticies and a list of triangles
+//! indexing into them, all little endian. Legacy (version 1) files have no
+//! header and start directly with the vertex count. Version 2 files start
+//! with the following header:
+//!
+//! ```text
+//! Offset  Size  Field
+//!      0     8  Magic, `FALKVBO\0`
+//!      8     4  Format version, currently 2
+//!     12     4  Header size in bytes, the vertex count follows the header
+//!     16     4  Map id, `0xffffffff` if unknown
+//!     20     4  Axis convention, see `AxisConvention`
+//!     24    12  Bounding box minimum x, y, z
+//!     36    12  Bounding box maximum x, y, z
+//!     48     4  Length of the map name in bytes
+//!     52     4  Number of metadata entries
+//!     56     *  Map name (UTF-8), followed by the metadata entries each
+//!               encoded as a u32 length and UTF-8 bytes for the key and
+//!               then the same for the value, padded with zeros to a
+//!               multiple of 8 bytes
+//! ```
+//!
+//! After the header (or at the start of a legacy file) is a u64 vertex count,
+//! the vertices as `[f32; 3]`, a u64 triangle count, and the triangles as
+//! `[u32; 3]`.
+//!
+//! `load` decodes the file element by element, `map` memory maps it and uses
+//! the data in place where possible. Both validate the mesh before returning
+//! it so that nothing out of bounds is ever handed to the GPU.
+
+mod read;
+mod write;
+
+pub use read::{Error, Issues, Report, MappedVbo, validate, load, map, check};
+pub use write::write;
+
+/// Magic at the start of versioned falkvbo files
+pub const MAGIC: [u8; 8] = *b"FALKVBO\0";
+
+/// Newest version of the format, and the one which is written
+pub const VERSION: u32 = 2;
+
+/// Size of the fixed portion of the header, before the variable length data
+const FIXED_HEADER_SIZE: usize = 56;
+
+/// Map id stored when the source map is unknown
+const UNKNOWN_MAP_ID: u32 = !0;
+
+/// A vertex
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct Vertex(pub f32, pub f32, pub f32);
+
+impl Eq for Vertex {}
+
+impl PartialOrd for Vertex {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Vertex {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.0, self.1, self.2).partial_cmp(&(other.0, other.1, other.2))
+            .expect("Compare failed for Vertex")
+    }
+}
+
+/// A triangle mesh
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Mesh {
+    /// Unique verticies
+    pub vertices: Vec<Vertex>,
+
+    /// Triangles as indicies into `vertices`
+    pub triangles: Vec<[u32; 3]>,
+}
+
+/// How the coordinates in the file map to the game world
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AxisConvention {
+    /// Recast/OpenGL style, `y` is up, `x` is the game's `y` and `z` is the
+    /// game's `x`. This is what the mangos RecastDemo exporter produces.
+    RecastYUp,
+
+    /// Game style, `z` is up
+    GameZUp,
+}
+
+impl AxisConvention {
+    /// Get the axis convention from its on-disk value
+    fn from_u32(val: u32) -> Option<Self> {
+        match val {
+            0 => Some(AxisConvention::RecastYUp),
+            1 => Some(AxisConvention::GameZUp),
+            _ => None,
+        }
+    }
+
+    /// Get the on-disk value of the axis convention
+    fn to_u32(self) -> u32 {
+        match self {
+            AxisConvention::RecastYUp => 0,
+            AxisConvention::GameZUp   => 1,
+        }
+    }
+}
+
+/// Information stored in the header of a falkvbo file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    /// Format version of the file, 1 for legacy headerless files
+    pub version: u32,
+
+    /// Map id the mesh was generated from
+    pub map_id: Option<u32>,
+
+    /// Name of the map the mesh was generated from, may be empty
+    pub map_name: String,
+
+    /// Axis convention used by the vertices
+    pub axis: AxisConvention,
+
+    /// Minimum and maximum corners of the bounding box of all vertices
+    pub bbox: ([f32; 3], [f32; 3]),
+
+    /// Free-form key/value metadata
+    pub metadata: Vec<(String, String)>,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Header {
+            version:  VERSION,
+            map_id:   None,
+            map_name: String::new(),
+            axis:     AxisConvention::RecastYUp,
+            bbox:     ([0.; 3], [0.; 3]),
+            metadata: Vec::new(),
+        }
+    }
+}
+
+/// Compute the bounding box of `vertices`
+fn bounding_box<'a, I>(vertices: I) -> ([f32; 3], [f32; 3])
+        where I: IntoIterator<Item = &'a Vertex> {
+    let mut min   = [f32::MAX; 3];
+    let mut max   = [f32::MIN; 3];
+    let mut empty = true;
+    for vertex in vertices {
+        for (ii, &val) in [vertex.0, vertex.1, vertex.2].iter().enumerate() {
+            min[ii] = min[ii].min(val);
+            max[ii] = max[ii].max(val);
+        }
+        empty = false;
+    }
+
+    if empty {
+        ([0.; 3], [0.; 3])
+    } else {
+        (min, max)
+    }
+}
+
+/// Swizzle a `GameZUp` coordinate into the `RecastYUp` convention
+fn game_to_recast(vertex: Vertex) -> Vertex {
+    Vertex(vertex.1, vertex.2, vertex.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A temporary file which is deleted when dropped
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            TempFile(std::env::temp_dir().join(format!(
+                "falkvbo_test_{}_{}.falkvbo", std::process::id(), name)))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// A small mesh of two triangles sharing an edge
+    fn test_mesh() -> Mesh {
+        Mesh {
+            vertices: vec![
+                Vertex(0., 0., 0.),
+                Vertex(1., 0., 0.),
+                Vertex(1., 2., 1.),
+                Vertex(0., -3., 1.),
+            ],
+            triangles: vec![[0, 1, 2], [0, 2, 3]],
+        }
+    }
+
+    /// Header used for the test mesh
+    fn test_header() -> Header {
+        Header {
+            map_id:   Some(1),
+            map_name: "Kalimdor".into(),
+            metadata: vec![
+                ("generator".into(), "test".into()),
+                ("empty".into(), String::new()),
+            ],
+            ..Header::default()
+        }
+    }
+
+    #[test]
+    fn round_trip_load() {
+        let file = TempFile::new("round_trip_load");
+        write(&file.0, &test_header(), &test_mesh()).unwrap();
+
+        let (header, mesh) = load(&file.0).unwrap();
+        assert_eq!(mesh, test_mesh());
+        assert_eq!(header, Header {
+            bbox: ([0., -3., 0.], [1., 2., 1.]),
+            ..test_header()
+        });
+    }
+
+    #[test]
+    fn round_trip_map() {
+        let file = TempFile::new("round_trip_map");
+        write(&file.0, &test_header(), &test_mesh()).unwrap();
+
+        let vbo = map(&file.0).unwrap();
+        assert_eq!(vbo.vertices(), &test_mesh().vertices[..]);
+        assert_eq!(vbo.triangles(), &test_mesh().triangles[..]);
+        assert_eq!(vbo.header.map_name, "Kalimdor");
+        assert_eq!(vbo.header.metadata, test_header().metadata);
+    }
+
+    #[test]
+    fn round_trip_empty() {
+        let file = TempFile::new("round_trip_empty");
+        write(&file.0, &Header::default(), &Mesh::default()).unwrap();
+
+        let (header, mesh) = load(&file.0).unwrap();
+        assert_eq!(mesh, Mesh::default());
+        assert_eq!(header, Header::default());
+    }
+
+    #[test]
+    fn game_axis_is_converted() {
+        let file = TempFile::new("game_axis_is_converted");
+        let header = Header { axis: AxisConvention::GameZUp, ..test_header() };
+        write(&file.0, &header, &test_mesh()).unwrap();
+
+        let expected: Vec<Vertex> = test_mesh().vertices.into_iter()
+            .map(game_to_recast).collect();
+        let (header, mesh) = load(&file.0).unwrap();
+        assert_eq!(header.axis, AxisConvention::RecastYUp);
+        assert_eq!(mesh.vertices, expected);
+        assert_eq!(map(&file.0).unwrap().vertices(), &expected[..]);
+    }
+
+    #[test]
+    fn legacy_files_load() {
+        let file = TempFile::new("legacy_files_load");
+        let mut data = Vec::new();
+        data.extend_from_slice(&1u64.to_le_bytes());
+        for val in &[1f32, 2., 3.] {
+            data.extend_from_slice(&val.to_le_bytes());
+        }
+        data.extend_from_slice(&1u64.to_le_bytes());
+        for val in &[0u32, 0, 0] {
+            data.extend_from_slice(&val.to_le_bytes());
+        }
+        std::fs::write(&file.0, &data).unwrap();
+
+        let (header, mesh) = load(&file.0).unwrap();
+        assert_eq!(header.version, 1);
+        assert_eq!(header.bbox, ([1., 2., 3.], [1., 2., 3.]));
+        assert_eq!(mesh.vertices, vec![Vertex(1., 2., 3.)]);
+        assert_eq!(mesh.triangles, vec![[0, 0, 0]]);
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let file = TempFile::new("truncated_files_are_rejected");
+        write(&file.0, &test_header(), &test_mesh()).unwrap();
+        let data = std::fs::read(&file.0).unwrap();
+
+        for len in 0..data.len() {
+            std::fs::write(&file.0, &data[..len]).unwrap();
+            assert!(load(&file.0).is_err(), "loaded at {} bytes", len);
+            assert!(map(&file.0).is_err(), "mapped at {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn invalid_meshes_are_reported() {
+        let file = TempFile::new("invalid_meshes_are_reported");
+        let mut mesh = test_mesh();
+        mesh.vertices[3].1 = f32::NAN;
+        mesh.triangles.push([0, 1, 4]);
+        mesh.triangles.push([0, 0, 1]);
+        write(&file.0, &test_header(), &mesh).unwrap();
+
+        let (_, report) = check(&file.0).unwrap();
+        assert!(report.has_errors());
+        assert_eq!(report.non_finite.examples, vec![3]);
+        assert_eq!(report.out_of_range.examples, vec![(2, 4)]);
+        assert_eq!(report.degenerate.examples, vec![3]);
+        assert!(matches!(load(&file.0), Err(Error::Invalid(_))));
+        assert!(matches!(map(&file.0), Err(Error::Invalid(_))));
+    }
+}
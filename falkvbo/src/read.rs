@@ -0,0 +1,630 @@
+//! Loading and validation of falkvbo files
+
+use std::io;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, BufReader, Cursor};
+use std::path::Path;
+use std::ops::Range;
+use std::convert::TryInto;
+
+use memmap2::Mmap;
+
+use crate::{Vertex, Mesh, Header, AxisConvention, MAGIC, VERSION};
+use crate::{FIXED_HEADER_SIZE, UNKNOWN_MAP_ID, bounding_box, game_to_recast};
+
+/// Number of examples of each kind of problem kept in a `Report`
+const MAX_EXAMPLES: usize = 8;
+
+/// Errors which can occur when loading a falkvbo file
+#[derive(Debug)]
+pub enum Error {
+    /// Accessing the file failed
+    Io(io::Error),
+
+    /// The file has no magic and isn't laid out like a legacy falkvbo file
+    NotFalkvbo,
+
+    /// The file has a format version we don't understand
+    UnsupportedVersion(u32),
+
+    /// The header is malformed
+    BadHeader(String),
+
+    /// The file ended in the middle of the named field
+    Truncated(&'static str),
+
+    /// A count is larger than the file could possibly hold
+    ImplausibleCount {
+        /// What is being counted
+        what: &'static str,
+
+        /// The count from the file
+        count: u64,
+
+        /// The largest count which would have fit
+        max: u64,
+    },
+
+    /// The mesh data failed validation
+    Invalid(Report),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::NotFalkvbo => write!(f,
+                "Not a falkvbo file, no magic and the size does not match a \
+                 legacy falkvbo file"),
+            Error::UnsupportedVersion(version) => write!(f,
+                "Unsupported falkvbo version {}, newest supported is {}",
+                version, VERSION),
+            Error::BadHeader(msg) => write!(f, "Bad falkvbo header: {}", msg),
+            Error::Truncated(what) => write!(f,
+                "Truncated falkvbo file, it ends in the {}", what),
+            Error::ImplausibleCount { what, count, max } => write!(f,
+                "Implausible {} count {}, the file has room for at most {}",
+                what, count, max),
+            Error::Invalid(report) => write!(f,
+                "Invalid falkvbo mesh:\n{}", report),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// Convert an I/O error from reading `what`, turning an unexpected EOF into a
+/// truncation error
+fn read_err(what: &'static str) -> impl Fn(io::Error) -> Error {
+    move |err| {
+        if err.kind() == io::ErrorKind::UnexpectedEof {
+            Error::Truncated(what)
+        } else {
+            Error::Io(err)
+        }
+    }
+}
+
+/// The number of times a kind of problem was found, and the first few
+/// examples of it
+#[derive(Debug, Clone)]
+pub struct Issues<T> {
+    /// Total number of occurrences
+    pub count: usize,
+
+    /// The first `MAX_EXAMPLES` occurrences
+    pub examples: Vec<T>,
+}
+
+impl<T> Default for Issues<T> {
+    fn default() -> Self {
+        Issues { count: 0, examples: Vec::new() }
+    }
+}
+
+impl<T> Issues<T> {
+    /// Record an occurrence of the problem
+    fn record(&mut self, example: T) {
+        self.count += 1;
+        if self.examples.len() < MAX_EXAMPLES {
+            self.examples.push(example);
+        }
+    }
+}
+
+/// Results of validating the mesh data in a falkvbo file
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Number of verticies in the mesh
+    pub num_verticies: usize,
+
+    /// Number of triangles in the mesh
+    pub num_triangles: usize,
+
+    /// Verticies with a NaN or infinite component, by vertex index
+    pub non_finite: Issues<usize>,
+
+    /// Triangles referencing a vertex past the end of the verticies, as the
+    /// triangle index and the bad vertex index
+    pub out_of_range: Issues<(usize, u32)>,
+
+    /// Triangles with a repeated vertex or zero area, by triangle index
+    pub degenerate: Issues<usize>,
+
+    /// Number of unexpected bytes after the triangle data
+    pub trailing_bytes: u64,
+}
+
+impl Report {
+    /// Returns `true` if the mesh is unsafe to use. Degenerate triangles are
+    /// harmless to draw, so they are only warnings.
+    pub fn has_errors(&self) -> bool {
+        self.non_finite.count > 0 || self.out_of_range.count > 0 ||
+            self.trailing_bytes > 0
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} verticies, {} triangles",
+                 self.num_verticies, self.num_triangles)?;
+
+        write!(f, "non-finite verticies:  {:10}", self.non_finite.count)?;
+        for idx in &self.non_finite.examples {
+            write!(f, " {}", idx)?;
+        }
+        writeln!(f)?;
+
+        write!(f, "out of range indicies: {:10}", self.out_of_range.count)?;
+        for (tri, idx) in &self.out_of_range.examples {
+            write!(f, " {}:{}", tri, idx)?;
+        }
+        writeln!(f)?;
+
+        write!(f, "degenerate triangles:  {:10}", self.degenerate.count)?;
+        for idx in &self.degenerate.examples {
+            write!(f, " {}", idx)?;
+        }
+        writeln!(f)?;
+
+        write!(f, "trailing bytes:        {:10}", self.trailing_bytes)
+    }
+}
+
+/// Validate mesh data, `trailing_bytes` is the number of bytes found after
+/// the end of the triangle data
+pub fn validate(vertices: &[Vertex], triangles: &[[u32; 3]],
+                trailing_bytes: u64) -> Report {
+    let mut report = Report {
+        num_verticies: vertices.len(),
+        num_triangles: triangles.len(),
+        trailing_bytes,
+        ..Default::default()
+    };
+
+    for (ii, vertex) in vertices.iter().enumerate() {
+        if !(vertex.0.is_finite() && vertex.1.is_finite() &&
+                vertex.2.is_finite()) {
+            report.non_finite.record(ii);
+        }
+    }
+
+    'next_triangle: for (ii, triangle) in triangles.iter().enumerate() {
+        for &idx in triangle {
+            if idx as usize >= vertices.len() {
+                report.out_of_range.record((ii, idx));
+                continue 'next_triangle;
+            }
+        }
+
+        let [a, b, c] = *triangle;
+        if a == b || b == c || a == c {
+            report.degenerate.record(ii);
+            continue;
+        }
+
+        // Check for zero area with the cross product of two of the edges
+        let [a, b, c] = [
+            vertices[a as usize], vertices[b as usize], vertices[c as usize]
+        ];
+        let ab = [b.0 - a.0, b.1 - a.1, b.2 - a.2];
+        let ac = [c.0 - a.0, c.1 - a.1, c.2 - a.2];
+        let cross = [
+            ab[1] * ac[2] - ab[2] * ac[1],
+            ab[2] * ac[0] - ab[0] * ac[2],
+            ab[0] * ac[1] - ab[1] * ac[0],
+        ];
+        if cross == [0.; 3] {
+            report.degenerate.record(ii);
+        }
+    }
+
+    report
+}
+
+/// Read a little endian u32
+fn read_u32<R: Read>(fd: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    fd.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+/// Read a little endian u64
+fn read_u64<R: Read>(fd: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    fd.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Read a little endian f32
+fn read_f32<R: Read>(fd: &mut R) -> io::Result<f32> {
+    let mut buf = [0u8; 4];
+    fd.read_exact(&mut buf)?;
+    Ok(f32::from_le_bytes(buf))
+}
+
+/// Read a u32 length prefixed UTF-8 string from the variable length portion
+/// of the header
+fn read_string(data: &mut &[u8]) -> Result<String, Error> {
+    let too_long = || {
+        Error::BadHeader("Metadata does not fit in the header".into())
+    };
+
+    let len = read_u32(data).map_err(|_| too_long())? as usize;
+    if len > data.len() {
+        return Err(too_long());
+    }
+
+    let (string, rest) = data.split_at(len);
+    *data = rest;
+    String::from_utf8(string.to_vec())
+        .map_err(|_| Error::BadHeader("Metadata is not UTF-8".into()))
+}
+
+/// Parse the version 2 header, after the magic has been consumed
+fn read_header<R: Read>(fd: &mut R) -> Result<Header, Error> {
+    let eof = read_err("header");
+
+    let version = read_u32(fd).map_err(&eof)?;
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+
+    let header_size = read_u32(fd).map_err(&eof)? as usize;
+    if header_size < FIXED_HEADER_SIZE {
+        return Err(Error::BadHeader(format!(
+            "Header size {} is smaller than the minimum {}",
+            header_size, FIXED_HEADER_SIZE)));
+    }
+
+    let map_id = read_u32(fd).map_err(&eof)?;
+    let axis   = read_u32(fd).map_err(&eof)?;
+    let axis   = AxisConvention::from_u32(axis).ok_or_else(|| {
+        Error::BadHeader(format!("Unknown axis convention {}", axis))
+    })?;
+
+    let mut bbox = ([0f32; 3], [0f32; 3]);
+    for x in bbox.0.iter_mut().chain(bbox.1.iter_mut()) {
+        *x = read_f32(fd).map_err(&eof)?;
+    }
+
+    let name_len     = read_u32(fd).map_err(&eof)? as usize;
+    let num_metadata = read_u32(fd).map_err(&eof)? as usize;
+
+    // Read the rest of the header in one go so that the strings can't run
+    // off into the vertex data
+    let mut rest = vec![0u8; header_size - FIXED_HEADER_SIZE];
+    fd.read_exact(&mut rest).map_err(&eof)?;
+    if name_len > rest.len() {
+        return Err(Error::BadHeader(format!(
+            "Map name of {} bytes does not fit in the header", name_len)));
+    }
+    let map_name = String::from_utf8(rest[..name_len].to_vec())
+        .map_err(|_| Error::BadHeader("Map name is not UTF-8".into()))?;
+
+    let mut cursor   = &rest[name_len..];
+    let mut metadata = Vec::new();
+    for _ in 0..num_metadata {
+        let key = read_string(&mut cursor)?;
+        let val = read_string(&mut cursor)?;
+        metadata.push((key, val));
+    }
+
+    Ok(Header {
+        version,
+        map_id: if map_id == UNKNOWN_MAP_ID { None } else { Some(map_id) },
+        map_name,
+        axis,
+        bbox,
+        metadata,
+    })
+}
+
+/// Read an element count for an array of 12 byte `what` elements, followed
+/// by `trailer` bytes, and make sure there are no more than `max_count` and
+/// they fit in the file. Returns the byte range of the array.
+fn read_array<R: Read + Seek>(fd: &mut R, what: &'static str, max_count: u64,
+                              trailer: u64, file_size: u64)
+        -> Result<Range<u64>, Error> {
+    let count = read_u64(fd).map_err(read_err("counts"))?;
+    let start = fd.stream_position()?;
+
+    // The array must fit in the file
+    let room = file_size.saturating_sub(start).saturating_sub(trailer) / 12;
+    let max  = room.min(max_count);
+    if count > max {
+        return Err(Error::ImplausibleCount { what, count, max });
+    }
+
+    let end = start + count * 12;
+    fd.seek(SeekFrom::Start(end))?;
+    Ok(start..end)
+}
+
+/// Check if the file looks like a legacy headerless falkvbo file. These have
+/// no way to identify themselves, so the only check we have is whether the
+/// counts exactly account for the size of the file.
+fn is_legacy<R: Read + Seek>(fd: &mut R, file_size: u64) -> io::Result<bool> {
+    fd.seek(SeekFrom::Start(0))?;
+    let num_verticies = read_u64(fd)?;
+
+    // Find where the triangle count would be
+    let tri_count_offset = match num_verticies.checked_mul(12)
+            .and_then(|x| x.checked_add(8))
+            .filter(|&x| x.saturating_add(8) <= file_size) {
+        Some(offset) => offset,
+        None => return Ok(false),
+    };
+
+    fd.seek(SeekFrom::Start(tri_count_offset))?;
+    let num_triangles = read_u64(fd)?;
+    Ok(num_triangles.checked_mul(12)
+        .and_then(|x| x.checked_add(tri_count_offset + 8)) == Some(file_size))
+}
+
+/// Where the parts of a falkvbo file are
+struct Layout {
+    /// Header of the file
+    header: Header,
+
+    /// Byte range of the vertex array
+    verticies: Range<u64>,
+
+    /// Byte range of the triangle array
+    triangles: Range<u64>,
+
+    /// Number of bytes after the triangle array
+    trailing_bytes: u64,
+}
+
+/// Parse the header and counts of a falkvbo file of `file_size` bytes
+fn read_layout<R: Read + Seek>(fd: &mut R, file_size: u64)
+        -> Result<Layout, Error> {
+    // Figure out which version of the format this is
+    let mut magic = [0u8; 8];
+    fd.read_exact(&mut magic).map_err(read_err("magic"))?;
+
+    let header = if magic == MAGIC {
+        read_header(fd)?
+    } else if is_legacy(fd, file_size)? {
+        // Rewind to the vertex count at the start of the file
+        fd.seek(SeekFrom::Start(0))?;
+        Header {
+            version:  1,
+            map_id:   None,
+            map_name: String::new(),
+            axis:     AxisConvention::RecastYUp,
+            bbox:     ([0.; 3], [0.; 3]),
+            metadata: Vec::new(),
+        }
+    } else {
+        return Err(Error::NotFalkvbo);
+    };
+
+    // Get the array locations, leaving room for the triangle count after the
+    // vertex array. Indicies are 32-bit, so there can't be more verticies
+    // than they can address.
+    let verticies = read_array(fd, "vertex", 1 << 32, 8, file_size)?;
+    let triangles = read_array(fd, "triangle", u64::MAX, 0, file_size)?;
+
+    Ok(Layout {
+        header,
+        trailing_bytes: file_size - triangles.end,
+        verticies,
+        triangles,
+    })
+}
+
+/// Load a falkvbo file containing the unique verticies and a list of triangle
+/// indicies. The verticies are always returned in the `RecastYUp` axis
+/// convention.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<(Header, Mesh), Error> {
+    // Open the file
+    let fd = File::open(path)?;
+    let file_size = fd.metadata()?.len();
+    let mut fd = BufReader::new(fd);
+
+    let Layout { mut header, verticies, triangles, trailing_bytes } =
+        read_layout(&mut fd, file_size)?;
+
+    // Load the vertex data
+    let eof = read_err("verticies");
+    fd.seek(SeekFrom::Start(verticies.start))?;
+    let num_verticies = (verticies.end - verticies.start) / 12;
+    let mut vertices = Vec::with_capacity(num_verticies as usize);
+    for _ in 0..num_verticies {
+        let x = read_f32(&mut fd).map_err(&eof)?;
+        let y = read_f32(&mut fd).map_err(&eof)?;
+        let z = read_f32(&mut fd).map_err(&eof)?;
+        vertices.push(Vertex(x, y, z));
+    }
+
+    // Load the triangle data
+    let eof = read_err("triangles");
+    fd.seek(SeekFrom::Start(triangles.start))?;
+    let num_triangles = (triangles.end - triangles.start) / 12;
+    let mut triangles = Vec::with_capacity(num_triangles as usize);
+    for _ in 0..num_triangles {
+        let a = read_u32(&mut fd).map_err(&eof)?;
+        let b = read_u32(&mut fd).map_err(&eof)?;
+        let c = read_u32(&mut fd).map_err(&eof)?;
+        triangles.push([a, b, c]);
+    }
+
+    let report = validate(&vertices, &triangles, trailing_bytes);
+    if report.has_errors() {
+        return Err(Error::Invalid(report));
+    }
+
+    to_recast(&mut header, &mut vertices);
+
+    // Legacy files don't store a bounding box, so compute one
+    if header.version == 1 {
+        header.bbox = bounding_box(&vertices);
+    }
+
+    Ok((header, Mesh { vertices, triangles }))
+}
+
+/// Convert `vertices` to the `RecastYUp` axis convention, updating `header`
+/// to match
+fn to_recast(header: &mut Header, vertices: &mut [Vertex]) {
+    if header.axis == AxisConvention::GameZUp {
+        vertices.iter_mut().for_each(|x| *x = game_to_recast(*x));
+
+        let (min, max) = header.bbox;
+        let min = game_to_recast(Vertex(min[0], min[1], min[2]));
+        let max = game_to_recast(Vertex(max[0], max[1], max[2]));
+        header.bbox = ([min.0, min.1, min.2], [max.0, max.1, max.2]);
+        header.axis = AxisConvention::RecastYUp;
+    }
+}
+
+/// Backing storage for the vertex and triangle data of a `MappedVbo`
+enum Storage {
+    /// The file is memory mapped and the data is used in place, the ranges
+    /// are byte offsets of the vertex and triangle arrays in the file
+    Mapped {
+        map:       Mmap,
+        verticies: Range<usize>,
+        triangles: Range<usize>,
+    },
+
+    /// The data was bulk read and converted to native arrays, used when the
+    /// file data can't be used as-is
+    Owned {
+        vertices:  Vec<Vertex>,
+        triangles: Vec<[u32; 3]>,
+    },
+}
+
+/// A falkvbo file whose vertex and triangle data is available as slices which
+/// can be handed directly to the GPU
+pub struct MappedVbo {
+    /// Header describing the mesh
+    pub header: Header,
+
+    /// Where the vertex and triangle data lives
+    storage: Storage,
+}
+
+impl MappedVbo {
+    /// Get the verticies, always in the `RecastYUp` axis convention
+    pub fn vertices(&self) -> &[Vertex] {
+        match &self.storage {
+            Storage::Mapped { map, verticies, .. } => {
+                let bytes = &map[verticies.clone()];
+                // Safety: `map` checked the alignment, size and host
+                // endianness, `Vertex` is `repr(C)` and any bit pattern is a
+                // valid f32
+                unsafe {
+                    std::slice::from_raw_parts(bytes.as_ptr() as *const _,
+                                               bytes.len() / 12)
+                }
+            }
+            Storage::Owned { vertices, .. } => vertices,
+        }
+    }
+
+    /// Get the triangles as indicies into `vertices()`
+    pub fn triangles(&self) -> &[[u32; 3]] {
+        match &self.storage {
+            Storage::Mapped { map, triangles, .. } => {
+                let bytes = &map[triangles.clone()];
+                // Safety: Same as for `vertices()`
+                unsafe {
+                    std::slice::from_raw_parts(bytes.as_ptr() as *const _,
+                                               bytes.len() / 12)
+                }
+            }
+            Storage::Owned { triangles, .. } => triangles,
+        }
+    }
+}
+
+/// Decode `bytes` as little endian triples of 32-bit values
+fn decode_triples<T, F>(bytes: &[u8], decode: F) -> Vec<[T; 3]>
+        where F: Fn([u8; 4]) -> T {
+    bytes.chunks_exact(12).map(|x| {
+        let word = |ii: usize| decode(x[ii..ii + 4].try_into().unwrap());
+        [word(0), word(4), word(8)]
+    }).collect()
+}
+
+/// Memory map a falkvbo file and validate it, without failing if the mesh
+/// data itself is invalid
+fn map_unchecked(path: &Path) -> Result<(MappedVbo, Report), Error> {
+    let fd = File::open(path)?;
+
+    // Safety: The mapping is read-only. As with any mapped file, if another
+    // process truncates or rewrites the file while we have it mapped we will
+    // see garbage or fault, which we accept for a viewer.
+    let map = unsafe { Mmap::map(&fd)? };
+    let Layout { mut header, verticies, triangles, trailing_bytes } =
+        read_layout(&mut Cursor::new(&map[..]), map.len() as u64)?;
+    let verticies = verticies.start as usize..verticies.end as usize;
+    let triangles = triangles.start as usize..triangles.end as usize;
+
+    // We can only use the data in place if it's already what the GPU expects
+    let in_place = cfg!(target_endian = "little") &&
+        header.axis == AxisConvention::RecastYUp &&
+        map[verticies.start..].as_ptr().align_offset(4) == 0 &&
+        map[triangles.start..].as_ptr().align_offset(4) == 0;
+
+    let storage = if in_place {
+        Storage::Mapped { map, verticies, triangles }
+    } else {
+        let mut vertices: Vec<Vertex> =
+            decode_triples(&map[verticies], f32::from_le_bytes)
+            .into_iter().map(|[x, y, z]| Vertex(x, y, z)).collect();
+        let triangles = decode_triples(&map[triangles], u32::from_le_bytes);
+        to_recast(&mut header, &mut vertices);
+
+        Storage::Owned { vertices, triangles }
+    };
+
+    let mut ret = MappedVbo { header, storage };
+    let report = validate(ret.vertices(), ret.triangles(), trailing_bytes);
+
+    // Legacy files don't store a bounding box, so compute one
+    if ret.header.version == 1 {
+        ret.header.bbox = bounding_box(ret.vertices());
+    }
+
+    Ok((ret, report))
+}
+
+/// Memory map a falkvbo file, validating it so that the vertex and triangle
+/// data can be used without decoding each element.
+///
+/// On big endian hosts, or if the data can't be used in place, the arrays
+/// are instead decoded from the mapping in bulk.
+pub fn map<P: AsRef<Path>>(path: P) -> Result<MappedVbo, Error> {
+    let (vbo, report) = map_unchecked(path.as_ref())?;
+    if report.has_errors() {
+        return Err(Error::Invalid(report));
+    }
+    Ok(vbo)
+}
+
+/// Check a falkvbo file, returning its header and a report of any problems
+/// with the mesh data. Only errors which prevent the mesh data from being
+/// found at all are returned as errors.
+pub fn check<P: AsRef<Path>>(path: P) -> Result<(Header, Report), Error> {
+    let (vbo, report) = map_unchecked(path.as_ref())?;
+    Ok((vbo.header, report))
+}
@@ -0,0 +1,75 @@
+//! Writing of falkvbo files
+
+use std::io;
+use std::fs::File;
+use std::io::{Write, BufWriter};
+use std::path::Path;
+
+use crate::{Mesh, Header, MAGIC, VERSION, FIXED_HEADER_SIZE, UNKNOWN_MAP_ID};
+use crate::bounding_box;
+
+/// Write `mesh` to a falkvbo file at `path`, in the newest version of the
+/// format.
+///
+/// The map information, axis convention and metadata are taken from
+/// `header`. Its version and bounding box are ignored, the bounding box is
+/// always computed from `mesh`.
+pub fn write<P: AsRef<Path>>(path: P, header: &Header, mesh: &Mesh)
+        -> io::Result<()> {
+    let (bbox_min, bbox_max) = bounding_box(&mesh.vertices);
+
+    // Encode the variable length portion of the header
+    let mut strings = Vec::new();
+    strings.extend_from_slice(header.map_name.as_bytes());
+    for (key, val) in &header.metadata {
+        for string in &[key, val] {
+            strings.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            strings.extend_from_slice(string.as_bytes());
+        }
+    }
+
+    // Pad the header to keep the vertex data 8-byte aligned
+    let padding = (8 - (FIXED_HEADER_SIZE + strings.len()) % 8) % 8;
+    strings.resize(strings.len() + padding, 0);
+
+    // Create the output file
+    let mut outfd = BufWriter::new(File::create(path)?);
+
+    // Write the header
+    outfd.write_all(&MAGIC)?;
+    outfd.write_all(&VERSION.to_le_bytes())?;
+    outfd.write_all(&((FIXED_HEADER_SIZE + strings.len()) as u32)
+        .to_le_bytes())?;
+    outfd.write_all(&header.map_id.unwrap_or(UNKNOWN_MAP_ID).to_le_bytes())?;
+    outfd.write_all(&header.axis.to_u32().to_le_bytes())?;
+    for val in bbox_min.iter().chain(bbox_max.iter()) {
+        outfd.write_all(&val.to_le_bytes())?;
+    }
+    outfd.write_all(&(header.map_name.len() as u32).to_le_bytes())?;
+    outfd.write_all(&(header.metadata.len() as u32).to_le_bytes())?;
+    outfd.write_all(&strings)?;
+
+    // Write the number of verticies
+    outfd.write_all(&(mesh.vertices.len() as u64).to_le_bytes())?;
+
+    // Write all verticies
+    for vertex in &mesh.vertices {
+        // Write x, y, z data
+        outfd.write_all(&vertex.0.to_le_bytes())?;
+        outfd.write_all(&vertex.1.to_le_bytes())?;
+        outfd.write_all(&vertex.2.to_le_bytes())?;
+    }
+
+    // Write the number of triangles
+    outfd.write_all(&(mesh.triangles.len() as u64).to_le_bytes())?;
+
+    // Write all triangles
+    for triangle in &mesh.triangles {
+        // Write vertex index data
+        for idx in triangle {
+            outfd.write_all(&idx.to_le_bytes())?;
+        }
+    }
+
+    outfd.flush()
+}
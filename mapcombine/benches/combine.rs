@@ -0,0 +1,151 @@
+//! Time combining a set of tiles serially, in parallel, and with the old
+//! `BTreeMap` based deduplication, and optimizing the result for the vertex
+//! cache
+//!
+//! Run with `cargo bench --bench combine [-- <directory of .obj files>]`.
+//! Without a directory, a synthetic tile set is generated in the temp
+//! directory.
+
+#![allow(clippy::print_with_newline, clippy::write_with_newline)]
+
+use std::fs::File;
+use std::io::{self, Write, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::hint::black_box;
+use std::collections::BTreeMap;
+
+use falkvbo::Vertex;
+use mapcombine::obj::ObjMesh;
+use mapcombine::combine::Combined;
+use mapcombine::optimize;
+
+/// Number of synthetic tiles along each axis
+const SYNTHETIC_TILES: u32 = 8;
+
+/// Number of grid cells along each axis of a synthetic tile
+const SYNTHETIC_CELLS: u32 = 128;
+
+/// Number of times each combiner is run
+const ITERS: u32 = 3;
+
+/// Write a synthetic tile at `tx`, `ty` to `path`. Neighbouring tiles share
+/// the verticies and triangles on their borders, like the mmap generator's
+/// tiles do.
+fn write_synthetic(path: &Path, tx: u32, ty: u32) -> io::Result<()> {
+    let mut fd = BufWriter::new(File::create(path)?);
+
+    // Overlap the neighbouring tiles by one cell
+    let width = SYNTHETIC_CELLS + 2;
+    for ii in 0..width * width {
+        let x = (tx * SYNTHETIC_CELLS + ii % width) as f32;
+        let z = (ty * SYNTHETIC_CELLS + ii / width) as f32;
+        let y = (x * 0.1).sin() * (z * 0.1).cos() * 10.;
+        write!(fd, "v {} {} {}\n", x, y, z)?;
+    }
+
+    // Two triangles per grid cell
+    for ii in 0..(width - 1) * (width - 1) {
+        let x = ii % (width - 1);
+        let z = ii / (width - 1);
+        let a = z * width + x + 1;
+        write!(fd, "f {} {} {}\n", a, a + width, a + 1)?;
+        write!(fd, "f {} {} {}\n", a + 1, a + width, a + width + 1)?;
+    }
+
+    fd.flush()
+}
+
+/// Combine `paths` the way mapcombine used to, with `BTreeMap`s of full
+/// verticies on a single thread
+fn combine_btree(paths: &[PathBuf]) -> usize {
+    let mut triangles: BTreeMap<(Vertex, Vertex, Vertex), ()> =
+        BTreeMap::new();
+    for path in paths {
+        let mesh = ObjMesh::load(path).unwrap();
+        for &[a, b, c] in &mesh.triangles {
+            triangles.insert((mesh.verticies[a as usize],
+                              mesh.verticies[b as usize],
+                              mesh.verticies[c as usize]), ());
+        }
+    }
+
+    let mut vertex_index: BTreeMap<Vertex, usize> = BTreeMap::new();
+    let mut indicies = Vec::new();
+    for &(a, b, c) in triangles.keys() {
+        for &x in &[a, b, c] {
+            let len = vertex_index.len();
+            indicies.push(*vertex_index.entry(x).or_insert(len));
+        }
+    }
+    indicies.len()
+}
+
+/// Run `func` `ITERS` times and print the average time it took
+fn bench<F: FnMut() -> usize>(name: &str, mut func: F) {
+    let start = Instant::now();
+    for _ in 0..ITERS {
+        black_box(func());
+    }
+    print!("{:>8} {:10.3} ms/iter\n", name,
+           start.elapsed().as_secs_f64() * 1000. / ITERS as f64);
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Use a user provided tile set if there is one, otherwise make one up
+    let user_dir = std::env::args().skip(1).find(|x| !x.starts_with('-'));
+    let (dir, synthetic) = match user_dir {
+        Some(dir) => (PathBuf::from(dir), false),
+        None => {
+            let dir = std::env::temp_dir().join(
+                format!("combine_bench_{}", std::process::id()));
+            std::fs::create_dir_all(&dir)?;
+            for ty in 0..SYNTHETIC_TILES {
+                for tx in 0..SYNTHETIC_TILES {
+                    write_synthetic(&dir.join(
+                        format!("map000{:02}{:02}.obj", tx, ty)), tx, ty)?;
+                }
+            }
+            (dir, true)
+        }
+    };
+
+    let mut paths = std::fs::read_dir(&dir)?
+        .map(|x| x.map(|x| x.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.retain(|x| x.extension().map(|x| x == "obj").unwrap_or(false));
+    paths.sort();
+
+    let mut combined = Combined::default();
+    combined.load_all(&paths, |_, _| {})?;
+    print!("{}: {} tiles, {} verticies, {} triangles, {} threads\n",
+           dir.display(), paths.len(), combined.num_verticies(),
+           combined.num_triangles(), rayon::current_num_threads());
+    let mesh = combined.into_mesh();
+
+    bench("btree", || combine_btree(&paths));
+    bench("serial", || {
+        let mut combined = Combined::default();
+        for path in &paths {
+            combined.load(path).unwrap();
+        }
+        combined.num_triangles()
+    });
+    bench("parallel", || {
+        let mut combined = Combined::default();
+        combined.load_all(&paths, |_, _| {}).unwrap();
+        combined.num_triangles()
+    });
+
+    bench("optimize", || {
+        let mut mesh = mesh.clone();
+        optimize::optimize(&mut mesh);
+        mesh.triangles.len()
+    });
+
+    if synthetic {
+        std::fs::remove_dir_all(&dir)?;
+    }
+
+    Ok(())
+}
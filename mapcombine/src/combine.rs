@@ -0,0 +1,404 @@
+//! Combining of many `.obj` tiles into a single deduplicated mesh
+//!
+//! Tiles are parsed in parallel a chunk at a time and merged into the output
+//! in input order, so only one chunk of parsed tiles is ever held in memory
+//! on top of the combined mesh itself, and the output does not depend on the
+//! number of threads.
+//!
+//! Verticies closer together than the weld tolerance are merged into the
+//! first one seen, which closes the cracks float noise leaves along tile
+//! seams. Triangles which collapse because of this are dropped.
+//!
+//! Triangles are kept with the winding and starting vertex they were authored
+//! with. Duplicates are dropped regardless of which vertex they start at. A
+//! triangle using the same verticies with the opposite winding as one already
+//! loaded (a back face) is the other side of double-sided geometry, so it is
+//! kept and only counted.
+
+use std::ops::Range;
+use std::path::Path;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use rayon::prelude::*;
+use falkvbo::{Vertex, Mesh};
+
+use crate::obj::{ObjMesh, ObjError};
+
+/// Number of tiles parsed in parallel per thread before they are merged
+const TILES_PER_THREAD: usize = 2;
+
+/// Get a key for `vertex` which is equal for equal verticies, treating `-0.0`
+/// and `0.0` as the same
+fn vertex_key(vertex: Vertex) -> [u32; 3] {
+    [(vertex.0 + 0.).to_bits(), (vertex.1 + 0.).to_bits(),
+     (vertex.2 + 0.).to_bits()]
+}
+
+/// Get the weld grid cell `vertex` falls in, for cells `weld` wide
+fn weld_cell(vertex: Vertex, weld: f32) -> [i64; 3] {
+    [(vertex.0 / weld).floor() as i64, (vertex.1 / weld).floor() as i64,
+     (vertex.2 / weld).floor() as i64]
+}
+
+/// Get the squared distance between `a` and `b`
+fn distance_squared(a: Vertex, b: Vertex) -> f32 {
+    (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2) + (a.2 - b.2).powi(2)
+}
+
+/// Rotate `tri` so it starts at its smallest index, which keeps its winding
+/// but makes all rotations of it equal
+fn canonical(tri: [u32; 3]) -> [u32; 3] {
+    let [a, b, c] = tri;
+    if a <= b && a <= c {
+        [a, b, c]
+    } else if b <= a && b <= c {
+        [b, c, a]
+    } else {
+        [c, a, b]
+    }
+}
+
+/// Get the verticies of `tri` sorted, which is the same for all rotations and
+/// both windings of it
+fn sorted(mut tri: [u32; 3]) -> [u32; 3] {
+    tri.sort_unstable();
+    tri
+}
+
+/// Multiple `.obj` files combined into a single mesh of unique verticies and
+/// unique triangles
+#[derive(Default)]
+pub struct Combined {
+    /// Unique verticies
+    verticies: Vec<Vertex>,
+
+    /// Lookup from a vertex key to its index in `verticies`, including the
+    /// keys of verticies which were welded to another one
+    vertex_index: HashMap<[u32; 3], u32>,
+
+    /// Distance under which verticies are welded together, 0 to only merge
+    /// identical verticies
+    weld: f32,
+
+    /// Indicies of the verticies in each weld grid cell, only used if `weld`
+    /// is non-zero
+    weld_grid: HashMap<[i64; 3], Vec<u32>>,
+
+    /// Number of distinct vertex positions which were welded to another
+    /// vertex
+    welded: usize,
+
+    /// Number of triangles dropped for using the same vertex more than once
+    degenerate: usize,
+
+    /// Unique triangles, as indicies into `verticies`
+    triangles: Vec<[u32; 3]>,
+
+    /// Lookup from the sorted verticies of each triangle in `triangles` to
+    /// the first triangle with them in its canonical rotation, and whether
+    /// its back face has been seen
+    triangle_set: HashMap<[u32; 3], ([u32; 3], bool)>,
+
+    /// Number of triangles dropped for being a copy of an earlier triangle,
+    /// possibly starting at a different vertex
+    duplicates: usize,
+
+    /// Number of triangles kept which are the back face of an earlier
+    /// triangle
+    back_faces: usize,
+
+    /// Names of the groups triangles were loaded from, prefixed with the name
+    /// of the file they came from, and the range of `triangles` which were
+    /// first seen in them
+    groups: Vec<(String, Range<usize>)>,
+}
+
+impl Combined {
+    /// Create an empty mesh which welds verticies closer than `weld`
+    /// together
+    pub fn new(weld: f32) -> Self {
+        assert!(weld.is_finite() && weld >= 0.,
+                "Weld tolerance must be finite and non-negative");
+        Combined { weld, ..Default::default() }
+    }
+
+    /// Number of unique verticies loaded so far
+    pub fn num_verticies(&self) -> usize {
+        self.verticies.len()
+    }
+
+    /// Number of unique triangles loaded so far
+    pub fn num_triangles(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Number of distinct vertex positions which were welded to another
+    /// vertex
+    pub fn num_welded(&self) -> usize {
+        self.welded
+    }
+
+    /// Number of triangles dropped for being degenerate, either in the input
+    /// or after welding
+    pub fn num_degenerate(&self) -> usize {
+        self.degenerate
+    }
+
+    /// Number of triangles dropped for being a copy of an earlier triangle
+    pub fn num_duplicates(&self) -> usize {
+        self.duplicates
+    }
+
+    /// Number of triangles kept which use the same verticies as an earlier
+    /// triangle, with the opposite winding
+    pub fn num_back_faces(&self) -> usize {
+        self.back_faces
+    }
+
+    /// Groups triangles were loaded from and the range of the unique
+    /// triangles which came from them
+    pub fn groups(&self) -> &[(String, Range<usize>)] {
+        &self.groups
+    }
+
+    /// Find the closest vertex within the weld tolerance of `vertex`
+    fn find_weld(&self, vertex: Vertex) -> Option<u32> {
+        let cell = weld_cell(vertex, self.weld);
+        let mut best: Option<(f32, u32)> = None;
+
+        // Cells are as wide as the tolerance, so anything in range is in
+        // this cell or a neighbouring one
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let neighbour = [cell[0] + dx, cell[1] + dy, cell[2] + dz];
+                    for &idx in self.weld_grid.get(&neighbour)
+                            .map(|x| &x[..]).unwrap_or(&[]) {
+                        let dist = distance_squared(
+                            vertex, self.verticies[idx as usize]);
+                        if dist <= self.weld * self.weld &&
+                                best.map(|x| dist < x.0).unwrap_or(true) {
+                            best = Some((dist, idx));
+                        }
+                    }
+                }
+            }
+        }
+
+        best.map(|x| x.1)
+    }
+
+    /// Get the index of `vertex`, adding it if it's new
+    fn vertex(&mut self, vertex: Vertex) -> u32 {
+        let key = vertex_key(vertex);
+        if let Some(&idx) = self.vertex_index.get(&key) {
+            return idx;
+        }
+
+        // Weld it to a nearby vertex if there is one
+        if self.weld > 0. {
+            if let Some(idx) = self.find_weld(vertex) {
+                self.welded += 1;
+                self.vertex_index.insert(key, idx);
+                return idx;
+            }
+        }
+
+        let idx = self.verticies.len() as u32;
+        self.verticies.push(vertex);
+        self.vertex_index.insert(key, idx);
+        if self.weld > 0. {
+            self.weld_grid.entry(weld_cell(vertex, self.weld))
+                .or_default().push(idx);
+        }
+        idx
+    }
+
+    /// Merge a parsed `.obj` file loaded from `path` into the mesh
+    pub fn add(&mut self, path: &Path, mesh: &ObjMesh) {
+        // Map the file's vertex indicies to ours, only for the verticies
+        // which are actually used by a triangle
+        let mut remap = vec![u32::MAX; mesh.verticies.len()];
+
+        for group in &mesh.groups {
+            let start = self.triangles.len();
+            for triangle in &mesh.triangles[group.triangles.clone()] {
+                let mut resolved = [0u32; 3];
+                for (out, &idx) in resolved.iter_mut().zip(triangle) {
+                    let idx = idx as usize;
+                    if remap[idx] == u32::MAX {
+                        remap[idx] = self.vertex(mesh.verticies[idx]);
+                    }
+                    *out = remap[idx];
+                }
+
+                // Drop triangles which collapsed to a line or a point
+                let [a, b, c] = resolved;
+                if a == b || b == c || a == c {
+                    self.degenerate += 1;
+                    continue;
+                }
+
+                match self.triangle_set.entry(sorted(resolved)) {
+                    Entry::Vacant(entry) => {
+                        entry.insert((canonical(resolved), false));
+                        self.triangles.push(resolved);
+                    }
+                    Entry::Occupied(mut entry) => {
+                        let (front, back_seen) = entry.get_mut();
+                        if *front == canonical(resolved) || *back_seen {
+                            self.duplicates += 1;
+                        } else {
+                            // Keep the other side of double-sided geometry
+                            *back_seen = true;
+                            self.back_faces += 1;
+                            self.triangles.push(resolved);
+                        }
+                    }
+                }
+            }
+
+            self.groups.push((format!("{}:{}", path.display(), group.name),
+                              start..self.triangles.len()));
+        }
+    }
+
+    /// Loads an object file from `path` into the mesh
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<(), ObjError> {
+        let path = path.as_ref();
+        let mesh = ObjMesh::load(path)?;
+        self.add(path, &mesh);
+        Ok(())
+    }
+
+    /// Loads all object files in `paths` into the mesh, parsing them in
+    /// parallel. The result is identical to calling `load` on each of them in
+    /// order. `progress` is called with the index of each file once it has
+    /// been merged.
+    pub fn load_all<P, F>(&mut self, paths: &[P], mut progress: F)
+            -> Result<(), ObjError>
+            where P: AsRef<Path> + Sync,
+                  F: FnMut(&Self, usize) {
+        let chunk_size = rayon::current_num_threads() * TILES_PER_THREAD;
+
+        for (chunk_idx, chunk) in paths.chunks(chunk_size).enumerate() {
+            // Parse the whole chunk in parallel
+            let meshes = chunk.par_iter()
+                .map(ObjMesh::load)
+                .collect::<Vec<_>>();
+
+            // Merge them in order
+            for (ii, (path, mesh)) in chunk.iter().zip(meshes).enumerate() {
+                self.add(path.as_ref(), &mesh?);
+                progress(self, chunk_idx * chunk_size + ii);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the combined mesh
+    pub fn into_mesh(self) -> Mesh {
+        Mesh {
+            vertices:  self.verticies,
+            triangles: self.triangles,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `data` as an `.obj` file named `name`
+    fn parse(name: &str, data: &str) -> ObjMesh {
+        ObjMesh::parse(Path::new(name), data).unwrap()
+    }
+
+    #[test]
+    fn shared_verticies_and_triangles_are_merged() {
+        let mut combined = Combined::default();
+        combined.add(Path::new("a"), &parse("a", "\
+v 0 0 0
+v 1 0 0
+v 0 0 1
+v -0 5 5
+f 1 2 3
+"));
+        combined.add(Path::new("b"), &parse("b", "\
+v 1 0 0
+v 0 0 -0
+v 0 0 1
+v 1 0 1
+f 2 1 3
+f 1 4 3
+f 2 1 3
+"));
+
+        assert_eq!(combined.groups(), &[
+            ("a:default".to_string(), 0..1),
+            ("b:default".to_string(), 1..2),
+        ]);
+        assert_eq!(combined.into_mesh(), Mesh {
+            vertices: vec![
+                Vertex(0., 0., 0.),
+                Vertex(1., 0., 0.),
+                Vertex(0., 0., 1.),
+                Vertex(1., 0., 1.),
+            ],
+            triangles: vec![[0, 1, 2], [1, 3, 2]],
+        });
+    }
+
+    #[test]
+    fn rotated_and_back_facing_duplicates_are_found() {
+        let mut combined = Combined::default();
+        combined.add(Path::new("a"), &parse("a", "\
+v 0 0 0
+v 1 0 0
+v 0 0 1
+v 1 0 1
+f 2 3 1
+f 1 2 3
+f 3 1 2
+f 3 2 1
+f 2 4 3
+f 2 1 3
+"));
+
+        assert_eq!(combined.num_duplicates(), 3);
+        assert_eq!(combined.num_back_faces(), 1);
+        assert_eq!(combined.into_mesh().triangles,
+                   vec![[0, 1, 2], [1, 0, 2], [0, 3, 1]]);
+    }
+
+    #[test]
+    fn close_verticies_are_welded() {
+        let mut combined = Combined::new(0.01);
+        combined.add(Path::new("a"), &parse("a", "\
+v 0 0 0
+v 1 0 0
+v 0 0 1
+v 0.005 0 0
+v 1.005 0 0.005
+v 2 0 0
+f 1 2 3
+f 4 5 3
+f 4 6 5
+f 1 4 2
+"));
+
+        assert_eq!(combined.num_welded(), 2);
+        assert_eq!(combined.num_degenerate(), 1);
+        assert_eq!(combined.into_mesh(), Mesh {
+            vertices: vec![
+                Vertex(0., 0., 0.),
+                Vertex(1., 0., 0.),
+                Vertex(0., 0., 1.),
+                Vertex(2., 0., 0.),
+            ],
+            triangles: vec![[0, 1, 2], [0, 3, 1]],
+        });
+    }
+}
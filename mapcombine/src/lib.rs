@@ -0,0 +1,7 @@
+//! Combines `.obj` files produced for RecastDemo from the mmap generation
+//! tools from mangos into falkvbo meshes
+
+pub mod obj;
+pub mod maps;
+pub mod combine;
+pub mod optimize;
@@ -1,162 +1,198 @@
-//! This contains a parser for `.obj` files produced for RecastDemo from the
-//! mmap generation tools from mangos
+//! Combines `.obj` files produced for RecastDemo from the mmap generation
+//! tools from mangos into falkvbo meshes
 
-use std::io;
-use std::fs::File;
-use std::io::{Write, BufWriter};
-use std::path::Path;
-use std::convert::TryInto;
-use std::collections::{BTreeSet, BTreeMap};
-
-/// A vertex
-#[repr(C)]
-#[derive(Default, Clone, Copy, Debug, PartialEq, PartialOrd)]
-pub struct Vertex(pub f32, pub f32, pub f32);
-
-impl Eq for Vertex {}
-
-impl Ord for Vertex {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).expect("Compare failed for Vertex")
-    }
-}
+#![allow(clippy::print_with_newline)]
 
-/// A representation of a `.obj` file, which contains vertex data and triangle
-/// data
-#[derive(Default)]
-pub struct ObjFile {
-    /// A list of all triangles and their verticies that compose them 
-    triangles: BTreeSet<(Vertex, Vertex, Vertex)>,
+use std::io;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use falkvbo::Header;
+use mapcombine::{maps, optimize};
+use mapcombine::combine::Combined;
+
+/// Default distance under which verticies are welded together
+const DEFAULT_WELD: f32 = 0.001;
+
+/// Cache size the vertex cache efficiency is reported for
+const ACMR_CACHE_SIZE: usize = 16;
+
+/// Usage text, `{}` is replaced with the program name
+const USAGE: &str = "\
+Usage: {} [options] <file.obj or directory>...
+
+Combines `.obj` files into falkvbo meshes. Inputs named like the mmap
+generator's tiles (`mapXXXYYZZ.obj`) are grouped by map id, and each map is
+written to its own file named after the map, eg. `Azeroth.falkvbo`. All other
+inputs are combined into `out.falkvbo`. Directories are searched for `.obj`
+files.
+
+Options:
+    -o, --output <path>    Output file if only one mesh is produced,
+                           otherwise the directory to write the meshes to.
+                           Defaults to the map name, or the current
+                           directory.
+    -w, --weld <distance>  Merge verticies closer together than this,
+                           closing cracks along tile seams. 0 only merges
+                           identical verticies. Defaults to 0.001.
+    -h, --help             Print this message
+";
+
+/// Parse a weld distance, which must be finite so the weld grid has more
+/// than one cell
+fn parse_weld(val: &str) -> Result<f32, String> {
+    val.parse().ok().filter(|x: &f32| x.is_finite() && *x >= 0.)
+        .ok_or_else(|| format!("Invalid weld distance {:?}", val))
 }
 
-impl ObjFile {
-    /// Loads an object file from `path` into the object file data
-    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        // Load the file contents
-        let data = std::fs::read_to_string(path)?;
-
-        // All verticies as they are loaded from the file
-        let mut verticies = Vec::new();
-
-        // Go through each line
-        for line in data.lines() {
-            if line.starts_with("v ") {
-                // Line contains vector data, parse it
-                // Currently we only support: v <x> <y> <z>
-                // We do not support `w` data
-                let mut parts = line[2..].split(" ");
-                let x: f32 = parts.next().unwrap().parse().unwrap();
-                let y: f32 = parts.next().unwrap().parse().unwrap();
-                let z: f32 = parts.next().unwrap().parse().unwrap();
-                assert!(parts.next().is_none(),
-                    "Whoa, unexpected vector data");
-
-                // Record the vertex
-                verticies.push(Vertex(x, y, z));
-            } else if line.starts_with("f ") {
-                // Line contains polygon face index data, parse it
-                // Currently we only support: p <a> <b> <c>
-                // We also only support positive indicies, technically negative
-                // indicies are possible and they are relative to the end of
-                // the vertex list, but we don't deal with those, no reason to
-                // parse them.
-                let mut parts = line[2..].split(" ");
-                let a: usize = parts.next().unwrap().parse().unwrap();
-                let b: usize = parts.next().unwrap().parse().unwrap();
-                let c: usize = parts.next().unwrap().parse().unwrap();
-                assert!(parts.next().is_none(),
-                    "Whoa, unexpected polygon face data");
-
-                // Resolve the triangle verticies
-                let a = verticies[a - 1];
-                let b = verticies[b - 1];
-                let c = verticies[c - 1];
-                self.triangles.insert((a, b, c));
-            } else {
-                unimplemented!("Unexpected obj file line: {}", line);
+/// Find the input files from the command line arguments, expanding
+/// directories into the `.obj` files in them
+fn find_inputs(args: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut inputs = Vec::new();
+    for arg in args {
+        if arg.is_dir() {
+            for entry in std::fs::read_dir(arg)? {
+                let path = entry?.path();
+                if path.extension().map(|x| x == "obj").unwrap_or(false) {
+                    inputs.push(path);
+                }
             }
+        } else {
+            inputs.push(arg.clone());
         }
-
-        Ok(())
     }
 
-    /// Create a list of unique verticies and indicies into them creating
-    /// triangles
-    pub fn write_vbo_index<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
-        // List of verticies
-        let mut verticies: Vec<Vertex> = Vec::new();
-
-        // Lookup table from vertex to index
-        let mut vertex_index: BTreeMap<Vertex, usize> = BTreeMap::new();
-
-        // List of triangles by index
-        let mut triangles: Vec<(u32, u32, u32)> = Vec::new();
-
-        // Go through each triangle in our data
-        for &(a, b, c) in &self.triangles {
-            for &x in &[a, b, c] {
-                // Save all unique verticies into the `verticies` list and
-                // maintain a lookup table from verticies to their indicies
-                if !vertex_index.contains_key(&x) {
-                    vertex_index.insert(x, verticies.len());
-                    verticies.push(x);
-                }
-            }
-
-            // Get the indicies into the verticies
-            let vertex_indicies: (u32, u32, u32) = (
-                vertex_index[&a].try_into().unwrap(),
-                vertex_index[&b].try_into().unwrap(),
-                vertex_index[&c].try_into().unwrap(),
-            );
-
-            // Save the triangle data based on index rather than actual vertex
-            // data
-            triangles.push(vertex_indicies);
-        }
+    // Sort for deterministic output regardless of directory order
+    inputs.sort();
+    Ok(inputs)
+}
 
-        // Create the output file
-        let mut outfd = BufWriter::new(File::create(path)?);
+/// Combine the `.obj` files in `inputs` into a single falkvbo at `output`
+fn combine(inputs: &[PathBuf], map_id: Option<u32>, weld: f32,
+           output: &Path) -> Result<(), Box<dyn Error>> {
+    // Load all the files, printing progress as they are merged
+    let start = Instant::now();
+    let mut combined = Combined::new(weld);
+    combined.load_all(inputs, |combined, idx| {
+        print!("[{:>w$}/{}] {} ({} verticies, {} triangles)\n",
+               idx + 1, inputs.len(), inputs[idx].display(),
+               combined.num_verticies(), combined.num_triangles(),
+               w = inputs.len().to_string().len());
+    })?;
+    print!("Loaded {} unique triangles from {} groups in {:.2?}\n",
+           combined.num_triangles(), combined.groups().len(),
+           start.elapsed());
+    print!("Welded {} verticies, dropped {} degenerate triangles\n",
+           combined.num_welded(), combined.num_degenerate());
+    print!("Dropped {} duplicate triangles, kept {} back faces\n",
+           combined.num_duplicates(), combined.num_back_faces());
+
+    // Reorder the mesh for the GPU's vertex cache
+    let start = Instant::now();
+    let mut mesh = combined.into_mesh();
+    let before = optimize::acmr(&mesh.triangles, ACMR_CACHE_SIZE);
+    optimize::optimize(&mut mesh);
+    print!("Optimized vertex cache ACMR {:.3} -> {:.3} in {:.2?}\n", before,
+           optimize::acmr(&mesh.triangles, ACMR_CACHE_SIZE), start.elapsed());
+
+    let header = Header {
+        map_id,
+        map_name: map_id.and_then(maps::map_name).unwrap_or("").into(),
+        metadata: vec![
+            ("generator".into(), "mapcombine".into()),
+            ("source_files".into(), inputs.len().to_string()),
+            ("weld".into(), weld.to_string()),
+        ],
+        ..Header::default()
+    };
+
+    print!("Writing {}\n", output.display());
+    falkvbo::write(output, &header, &mesh)?;
+    Ok(())
+}
 
-        // Write the number of verticies
-        outfd.write_all(&(verticies.len() as u64).to_le_bytes())?;
+/// Parse the arguments and combine the requested meshes
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args();
+    let prog = args.next().unwrap_or_else(|| "mapcombine".into());
 
-        // Write all verticies
-        for &vertex in &verticies {
-            // Write x, y, z data
-            outfd.write_all(&vertex.0.to_le_bytes())?;
-            outfd.write_all(&vertex.1.to_le_bytes())?;
-            outfd.write_all(&vertex.2.to_le_bytes())?;
-        }
-        
-        // Write the number of triangles
-        outfd.write_all(&(triangles.len() as u64).to_le_bytes())?;
-
-        // Write all triangles
-        for &triangle in &triangles {
-            // Write vertex index data
-            outfd.write_all(&triangle.0.to_le_bytes())?;
-            outfd.write_all(&triangle.1.to_le_bytes())?;
-            outfd.write_all(&triangle.2.to_le_bytes())?;
+    // Get the arguments
+    let mut output = None;
+    let mut weld   = DEFAULT_WELD;
+    let mut paths  = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", USAGE.replacen("{}", &prog, 1));
+                return Ok(());
+            }
+            "-o" | "--output" => {
+                output = Some(PathBuf::from(args.next()
+                    .ok_or_else(|| format!("{} requires a value", arg))?));
+            }
+            "-w" | "--weld" => {
+                weld = parse_weld(&args.next()
+                    .ok_or_else(|| format!("{} requires a value", arg))?)?;
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("Unknown option {}", arg).into());
+            }
+            _ => paths.push(PathBuf::from(arg)),
         }
-
-        Ok(())
     }
-}
 
-fn main() -> io::Result<()> {
-    // Get the arguments
-    let args: Vec<String> = std::env::args().collect();
+    let inputs = find_inputs(&paths)?;
+    if inputs.is_empty() {
+        return Err(format!("No input files\n\n{}",
+                           USAGE.replacen("{}", &prog, 1)).into());
+    }
 
-    // For each file, load it!
-    let mut obj = ObjFile::default();
-    for filename in &args[1..] {
-        print!("Loading {}\n", filename);
-        obj.load(filename)?;
+    // Group the inputs by the map they are tiles of
+    let mut by_map: BTreeMap<Option<u32>, Vec<PathBuf>> = BTreeMap::new();
+    for input in inputs {
+        let map_id = maps::parse_tile_name(&input).map(|x| x.0);
+        by_map.entry(map_id).or_default().push(input);
     }
 
-    obj.write_vbo_index("foop.falkvbo")?;
+    // Figure out where each map goes
+    let name = |map_id: Option<u32>| {
+        map_id.map(maps::output_name).unwrap_or_else(|| "out.falkvbo".into())
+    };
+    if by_map.len() == 1 {
+        let (&map_id, inputs) = by_map.iter().next().unwrap();
+        let output = output.unwrap_or_else(|| PathBuf::from(name(map_id)));
+        combine(inputs, map_id, weld, &output)?;
+    } else {
+        let dir = output.unwrap_or_else(|| PathBuf::from("."));
+        std::fs::create_dir_all(&dir)?;
+        for (&map_id, inputs) in &by_map {
+            combine(inputs, map_id, weld, &dir.join(name(map_id)))?;
+        }
+    }
 
     Ok(())
 }
 
+fn main() {
+    if let Err(err) = run() {
+        print!("{}\n", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weld_must_be_finite_and_non_negative() {
+        assert_eq!(parse_weld("0"), Ok(0.));
+        assert_eq!(parse_weld("0.25"), Ok(0.25));
+        for val in ["inf", "-inf", "NaN", "-1", "x"] {
+            assert_eq!(parse_weld(val),
+                       Err(format!("Invalid weld distance {:?}", val)));
+        }
+    }
+}
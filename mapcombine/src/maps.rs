@@ -0,0 +1,110 @@
+//! Map ids, their names, and the tile file naming used by the mmap generator
+
+use std::path::Path;
+
+/// Names of the maps by id, as used for the output filenames
+const MAP_NAMES: &[(u32, &str)] = &[
+    (  0, "Azeroth"),
+    (  1, "Kalimdor"),
+    ( 13, "test"),
+    ( 25, "ScottTest"),
+    ( 29, "Test"),
+    ( 30, "PVPZone01"),
+    ( 33, "Shadowfang"),
+    ( 34, "StormwindJail"),
+    ( 35, "StormwindPrison"),
+    ( 36, "DeadminesInstance"),
+    ( 37, "PVPZone02"),
+    ( 42, "Collin"),
+    ( 43, "WailingCaverns"),
+    ( 44, "Monastery"),
+    ( 47, "RazorfenKraulInstance"),
+    ( 48, "Blackfathom"),
+    ( 70, "Uldaman"),
+    ( 90, "GnomeragonInstance"),
+    (109, "SunkenTemple"),
+    (129, "RazorfenDowns"),
+    (169, "EmeraldDream"),
+    (189, "MonasteryInstances"),
+    (209, "TanarisInstance"),
+    (229, "BlackRockSpire"),
+    (230, "BlackrockDepths"),
+    (249, "OnyxiaLairInstance"),
+    (269, "CavernsOfTime"),
+    (289, "SchoolofNecromancy"),
+    (309, "Zul'gurub"),
+    (329, "Stratholme"),
+    (349, "Mauradon"),
+    (369, "DeeprunTram"),
+    (389, "OrgrimmarInstance"),
+    (409, "MoltenCore"),
+    (429, "DireMaul"),
+    (449, "AlliancePVPBarracks"),
+    (450, "HordePVPBarracks"),
+    (451, "development"),
+    (469, "BlackwingLair"),
+    (489, "PVPZone03"),
+    (509, "AhnQiraj"),
+    (529, "PVPZone04"),
+    (531, "AhnQirajTemple"),
+    (533, "Stratholme Raid"),
+];
+
+/// Get the name of the map with id `map_id`
+pub fn map_name(map_id: u32) -> Option<&'static str> {
+    MAP_NAMES.iter().find(|x| x.0 == map_id).map(|x| x.1)
+}
+
+/// Get the name to use for the output file of map `map_id`, falling back to
+/// the id for maps we don't know the name of
+pub fn output_name(map_id: u32) -> String {
+    match map_name(map_id) {
+        Some(name) => format!("{}.falkvbo", name),
+        None       => format!("map{:03}.falkvbo", map_id),
+    }
+}
+
+/// Parse a tile filename of the form `mapXXXYYZZ.obj`, where `XXX` is the
+/// map id and `YY` and `ZZ` are the tile coordinates. The extension can be
+/// in any case. Returns the map id and tile coordinates.
+pub fn parse_tile_name(path: &Path) -> Option<(u32, u32, u32)> {
+    if !path.extension()?.eq_ignore_ascii_case("obj") {
+        return None;
+    }
+    let name = path.file_stem()?.to_str()?;
+    let digits = name.strip_prefix("map")?;
+    if digits.len() != 7 || !digits.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+
+    Some((digits[..3].parse().ok()?,
+          digits[3..5].parse().ok()?,
+          digits[5..].parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_names_parse() {
+        let parse = |name: &str| parse_tile_name(Path::new(name));
+        assert_eq!(parse("map0003132.obj"), Some((0, 31, 32)));
+        assert_eq!(parse("tiles/map5300007.obj"), Some((530, 0, 7)));
+        assert_eq!(parse("map0003132.OBJ"), Some((0, 31, 32)));
+
+        for name in ["map000313.obj", "map00031320.obj", "map.obj",
+                     "map00031a2.obj", "map000+132.obj", "MAP0003132.obj",
+                     "map0003132.obj.bak", "map0003132", "map0003132.mmap",
+                     "xmap0003132.obj"] {
+            assert_eq!(parse(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn unknown_maps_are_named_by_id() {
+        assert_eq!(output_name(0), "Azeroth.falkvbo");
+        assert_eq!(output_name(9999), "map9999.falkvbo");
+        assert_eq!(output_name(7), "map007.falkvbo");
+    }
+}
@@ -0,0 +1,385 @@
+//! Parser for Wavefront `.obj` files
+//!
+//! Only the geometry is used. Vertex positions (`v`) and faces (`f`) are
+//! loaded, with faces in any of the `v`, `v/vt`, `v//vn` and `v/vt/vn`
+//! forms, positive or negative (relative) indicies, and any number of
+//! verticies. Polygons are triangulated as fans. Groups and objects (`g` and
+//! `o`) are recorded, everything else (normals, texture coordinates,
+//! materials, smoothing groups, ...) is skipped.
+
+use std::io;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use falkvbo::Vertex;
+
+/// Name of the group faces are in before any `g` or `o` statement
+const DEFAULT_GROUP: &str = "default";
+
+/// A named range of triangles
+#[derive(Debug, Clone)]
+pub struct ObjGroup {
+    /// Name of the group or object
+    pub name: String,
+
+    /// Indicies into `ObjMesh::triangles` of the triangles in this group
+    pub triangles: Range<usize>,
+}
+
+/// The geometry in an `.obj` file
+#[derive(Debug, Default)]
+pub struct ObjMesh {
+    /// All verticies in the order they appear in the file
+    pub verticies: Vec<Vertex>,
+
+    /// Triangles as zero-based indicies into `verticies`
+    pub triangles: Vec<[u32; 3]>,
+
+    /// Groups the triangles are in, in file order. Triangles are in exactly
+    /// one group, and empty groups are omitted.
+    pub groups: Vec<ObjGroup>,
+}
+
+/// The different problems which can be found in an `.obj` file
+#[derive(Debug)]
+pub enum ObjErrorKind {
+    /// Reading the file failed
+    Io(io::Error),
+
+    /// A number failed to parse
+    InvalidNumber(String),
+
+    /// A vertex had fewer than 3 coordinates
+    MissingCoordinate,
+
+    /// A face had fewer than 3 verticies
+    TooFewVerticies(usize),
+
+    /// A face vertex index was 0, or past the verticies defined so far
+    IndexOutOfRange(i64),
+}
+
+/// An error from parsing an `.obj` file, along with where it happened
+#[derive(Debug)]
+pub struct ObjError {
+    /// File the error occurred in
+    pub path: PathBuf,
+
+    /// One-based line number, 0 if the error is not about a specific line
+    pub line: usize,
+
+    /// What went wrong
+    pub kind: ObjErrorKind,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path.display())?;
+        if self.line != 0 {
+            write!(f, ":{}", self.line)?;
+        }
+        write!(f, ": ")?;
+
+        match &self.kind {
+            ObjErrorKind::Io(err) => write!(f, "{}", err),
+            ObjErrorKind::InvalidNumber(num) =>
+                write!(f, "Invalid number {:?}", num),
+            ObjErrorKind::MissingCoordinate =>
+                write!(f, "Vertex has fewer than 3 coordinates"),
+            ObjErrorKind::TooFewVerticies(num) =>
+                write!(f, "Face has {} verticies, at least 3 are needed", num),
+            ObjErrorKind::IndexOutOfRange(idx) =>
+                write!(f, "Vertex index {} is out of range", idx),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            ObjErrorKind::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a face vertex reference (`v`, `v/vt`, `v//vn` or `v/vt/vn`) into a
+/// zero-based vertex index, given `num_verticies` defined so far
+fn parse_face_vertex(vertex: &str, num_verticies: usize)
+        -> Result<u32, ObjErrorKind> {
+    // Only the position index matters to us
+    let idx = vertex.split('/').next().unwrap();
+    let idx: i64 = idx.parse()
+        .map_err(|_| ObjErrorKind::InvalidNumber(idx.into()))?;
+
+    // Positive indicies are one-based, negative indicies are relative to the
+    // end of the verticies loaded so far
+    let resolved = if idx > 0 {
+        idx - 1
+    } else {
+        num_verticies as i64 + idx
+    };
+
+    if idx == 0 || resolved < 0 || resolved >= num_verticies as i64 ||
+            resolved > u32::MAX as i64 {
+        return Err(ObjErrorKind::IndexOutOfRange(idx));
+    }
+    Ok(resolved as u32)
+}
+
+impl ObjMesh {
+    /// Start a new group named `name` for the following faces
+    fn start_group(&mut self, name: String) {
+        // Drop the previous group if nothing was put in it
+        if self.groups.last().map(|x| x.triangles.is_empty()) == Some(true) {
+            self.groups.pop();
+        }
+
+        let start = self.triangles.len();
+        self.groups.push(ObjGroup { name, triangles: start..start });
+    }
+
+    /// Parse a single logical line of an `.obj` file, without comments
+    fn parse_line(&mut self, line: &str) -> Result<(), ObjErrorKind> {
+        let mut parts = line.split_whitespace();
+
+        match parts.next() {
+            Some("v") => {
+                // Vertex: `v x y z [w]`. Some exporters put colours after the
+                // position, so anything after `z` is ignored. Coordinates
+                // must be finite as falkvbo refuses anything else.
+                let mut coord = || -> Result<f32, ObjErrorKind> {
+                    let coord = parts.next()
+                        .ok_or(ObjErrorKind::MissingCoordinate)?;
+                    coord.parse().ok().filter(|x: &f32| x.is_finite())
+                        .ok_or_else(|| {
+                            ObjErrorKind::InvalidNumber(coord.into())
+                        })
+                };
+                let x = coord()?;
+                let y = coord()?;
+                let z = coord()?;
+                self.verticies.push(Vertex(x, y, z));
+            }
+            Some("f") => {
+                let face = parts.map(|x| {
+                    parse_face_vertex(x, self.verticies.len())
+                }).collect::<Result<Vec<u32>, _>>()?;
+                if face.len() < 3 {
+                    return Err(ObjErrorKind::TooFewVerticies(face.len()));
+                }
+
+                // Triangulate the polygon as a fan around the first vertex
+                if self.groups.is_empty() {
+                    self.start_group(DEFAULT_GROUP.into());
+                }
+                for ii in 1..face.len() - 1 {
+                    self.triangles.push([face[0], face[ii], face[ii + 1]]);
+                }
+                self.groups.last_mut().unwrap().triangles.end =
+                    self.triangles.len();
+            }
+            Some("g") | Some("o") => {
+                // Groups can have multiple names, keep them all
+                let name = parts.collect::<Vec<_>>().join(" ");
+                self.start_group(if name.is_empty() {
+                    DEFAULT_GROUP.into()
+                } else {
+                    name
+                });
+            }
+            _ => {
+                // Blank lines, normals, texture coordinates, materials,
+                // smoothing groups, lines, points and anything else we don't
+                // need for the geometry
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse the contents of an `.obj` file
+    pub fn parse(path: &Path, data: &str) -> Result<Self, ObjError> {
+        let mut ret = ObjMesh::default();
+
+        // Lines ending in a backslash are continued on the next line. Comments
+        // are stripped first, so a backslash in one doesn't continue it.
+        let mut logical = String::new();
+        let mut start_line = 0;
+        for (line_num, line) in data.lines().enumerate() {
+            if logical.is_empty() {
+                start_line = line_num + 1;
+            }
+
+            let line = line.split('#').next().unwrap();
+            if let Some(line) = line.strip_suffix('\\') {
+                logical.push_str(line);
+                logical.push(' ');
+                continue;
+            }
+            logical.push_str(line);
+
+            ret.parse_line(&logical).map_err(|kind| ObjError {
+                path: path.into(),
+                line: start_line,
+                kind,
+            })?;
+            logical.clear();
+        }
+
+        // Handle a continuation on the last line
+        if !logical.is_empty() {
+            ret.parse_line(&logical).map_err(|kind| ObjError {
+                path: path.into(),
+                line: start_line,
+                kind,
+            })?;
+        }
+
+        // Drop a trailing empty group
+        if ret.groups.last().map(|x| x.triangles.is_empty()) == Some(true) {
+            ret.groups.pop();
+        }
+
+        Ok(ret)
+    }
+
+    /// Load and parse an `.obj` file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ObjError> {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path).map_err(|err| ObjError {
+            path: path.into(),
+            line: 0,
+            kind: ObjErrorKind::Io(err),
+        })?;
+        Self::parse(path, &data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `data` as an `.obj` file named `test.obj`
+    fn parse(data: &str) -> Result<ObjMesh, ObjError> {
+        ObjMesh::parse(Path::new("test.obj"), data)
+    }
+
+    /// Get the names and triangle ranges of the groups in `mesh`
+    fn groups(mesh: &ObjMesh) -> Vec<(&str, Range<usize>)> {
+        mesh.groups.iter().map(|x| (x.name.as_str(), x.triangles.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn face_vertex_forms_and_negative_indicies() {
+        let mesh = parse("\
+v 0 0 0
+v 1 0 0
+v 0 1 0
+v 0 0 1
+vt 0 0
+vn 0 1 0
+f 1/1/1 2/1/1 3/1/1
+f 1//1 3//1 4//1
+f 2/1 3/1 4/1
+f -4 -3 -1
+").unwrap();
+        assert_eq!(mesh.verticies.len(), 4);
+        assert_eq!(mesh.triangles,
+                   vec![[0, 1, 2], [0, 2, 3], [1, 2, 3], [0, 1, 3]]);
+    }
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let mesh = parse("\
+v 0 0 0
+v 1 0 0
+v 1 0 1
+v 0 0 1
+v -1 0 0
+f 1 2 3 4
+f 1 2 3 4 5
+").unwrap();
+        assert_eq!(mesh.triangles, vec![
+            [0, 1, 2], [0, 2, 3],
+            [0, 1, 2], [0, 2, 3], [0, 3, 4],
+        ]);
+    }
+
+    #[test]
+    fn whitespace_comments_and_continuations() {
+        let mesh = parse("
+v\t 1   2.5\t-3  1
+v 4 5 6 # a comment \\
+v 7 8 9 0.5 0.5 0.5
+# a comment line ending in a backslash \\
+f 1 \\
+  2 3
+").unwrap();
+        assert_eq!(mesh.verticies, vec![
+            Vertex(1., 2.5, -3.), Vertex(4., 5., 6.), Vertex(7., 8., 9.),
+        ]);
+        assert_eq!(mesh.triangles, vec![[0, 1, 2]]);
+    }
+
+    #[test]
+    fn groups_and_objects_are_tracked() {
+        let mesh = parse("\
+v 0 0 0
+v 1 0 0
+v 0 0 1
+f 1 2 3
+o first
+s 1
+usemtl stone
+mtllib stone.mtl
+f 1 2 3
+f 1 3 2
+g empty
+g two names
+f 1 2 3
+g
+f 1 2 3
+g trailing
+").unwrap();
+        assert_eq!(groups(&mesh), vec![
+            ("default", 0..1),
+            ("first", 1..3),
+            ("two names", 3..4),
+            ("default", 4..5),
+        ]);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let check = |data: &str, line: usize, kind: fn(&ObjErrorKind) -> bool| {
+            let err = parse(data).unwrap_err();
+            assert_eq!((err.path.as_path(), err.line),
+                       (Path::new("test.obj"), line), "{}", err);
+            assert!(kind(&err.kind), "{}", err);
+        };
+
+        let verts = "v 0 0 0\nv 1 0 0\nv 0 0 1\n";
+        check(&format!("{}f 1 2 4\n", verts), 4,
+              |x| matches!(x, ObjErrorKind::IndexOutOfRange(4)));
+        check(&format!("{}\nf 1 2 0\n", verts), 5,
+              |x| matches!(x, ObjErrorKind::IndexOutOfRange(0)));
+        check(&format!("{}f 1 -4 2\n", verts), 4,
+              |x| matches!(x, ObjErrorKind::IndexOutOfRange(-4)));
+        check(&format!("{}f 1 \\\n 2\n", verts), 4,
+              |x| matches!(x, ObjErrorKind::TooFewVerticies(2)));
+        check("v 0 0\n", 1, |x| matches!(x, ObjErrorKind::MissingCoordinate));
+        check("v 0 0 0\nv 0 nan 0\n", 2,
+              |x| matches!(x,
+                  ObjErrorKind::InvalidNumber(num) if num == "nan"));
+        check("v inf 0 0\n", 1,
+              |x| matches!(x,
+                  ObjErrorKind::InvalidNumber(num) if num == "inf"));
+
+        let err = parse(&format!("{}f 1 2 4\n", verts)).unwrap_err();
+        assert_eq!(err.to_string(),
+                   "test.obj:4: Vertex index 4 is out of range");
+    }
+}
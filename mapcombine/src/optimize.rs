@@ -0,0 +1,295 @@
+//! Reordering of meshes for GPU vertex cache locality
+//!
+//! Triangles are reordered with Tom Forsyth's "Linear-Speed Vertex Cache
+//! Optimisation", which greedily emits the triangle whose verticies score
+//! best given a simulated LRU cache, favouring verticies which are in the
+//! cache and verticies with few triangles left to emit. Verticies are then
+//! renumbered in the order the triangles first use them so vertex fetches are
+//! close together too. The triangles themselves, including their winding,
+//! are left untouched. Degenerate triangles, which reuse a vertex, aren't
+//! scored and go last.
+
+use falkvbo::{Vertex, Mesh};
+
+/// Size of the simulated LRU vertex cache
+const CACHE_SIZE: usize = 32;
+
+/// Exponent the score of a vertex falls off with by cache position
+const CACHE_DECAY_POWER: f32 = 1.5;
+
+/// Score of the verticies used by the last triangle emitted. This is lower
+/// than the next position so strips don't double back on themselves.
+const LAST_TRI_SCORE: f32 = 0.75;
+
+/// Scale of the boost given to verticies with few triangles left
+const VALENCE_BOOST_SCALE: f32 = 2.;
+
+/// Exponent of the boost given to verticies with few triangles left
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+/// Score a vertex at `cache_pos` in the cache (`None` if not in it) with
+/// `valence` triangles left to emit
+fn vertex_score(cache_pos: Option<usize>, valence: u32) -> f32 {
+    // Verticies with nothing left to emit don't matter
+    if valence == 0 {
+        return -1.;
+    }
+
+    let cache_score = match cache_pos {
+        None => 0.,
+        Some(pos) if pos < 3 => LAST_TRI_SCORE,
+        Some(pos) => {
+            let scale = 1. / (CACHE_SIZE - 3) as f32;
+            (1. - (pos - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+    };
+
+    cache_score +
+        VALENCE_BOOST_SCALE * (valence as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Check if `tri` uses a vertex more than once
+fn is_degenerate(tri: [u32; 3]) -> bool {
+    tri[0] == tri[1] || tri[1] == tri[2] || tri[2] == tri[0]
+}
+
+/// Get the order to emit `triangles` in, as indicies into `triangles`
+fn forsyth_order(triangles: &[[u32; 3]], num_verticies: usize) -> Vec<u32> {
+    // Degenerate triangles would be in the cache and adjacency lists twice,
+    // so leave them out and emit them at the end
+    let mut emitted: Vec<bool> = triangles.iter()
+        .map(|&x| is_degenerate(x)).collect();
+    let to_emit = emitted.iter().filter(|&&x| !x).count();
+
+    // Build a list of the triangles using each vertex, as offsets into a
+    // single array
+    let mut valence = vec![0u32; num_verticies];
+    for (tri, _) in triangles.iter().zip(&emitted).filter(|x| !*x.1) {
+        for &idx in tri {
+            valence[idx as usize] += 1;
+        }
+    }
+    let mut offsets = Vec::with_capacity(num_verticies + 1);
+    offsets.push(0usize);
+    for &count in &valence {
+        offsets.push(offsets.last().unwrap() + count as usize);
+    }
+    let mut adjacency = vec![0u32; offsets[num_verticies]];
+    let mut fill = offsets.clone();
+    for (tri_idx, tri) in triangles.iter().enumerate() {
+        if emitted[tri_idx] {
+            continue;
+        }
+        for &idx in tri {
+            adjacency[fill[idx as usize]] = tri_idx as u32;
+            fill[idx as usize] += 1;
+        }
+    }
+
+    // Initial scores, nothing is in the cache
+    let mut cache_pos: Vec<Option<usize>> = vec![None; num_verticies];
+    let mut score: Vec<f32> = valence.iter()
+        .map(|&x| vertex_score(None, x)).collect();
+    let mut tri_score: Vec<f32> = triangles.iter()
+        .map(|x| x.iter().map(|&x| score[x as usize]).sum()).collect();
+
+    let mut order = Vec::with_capacity(triangles.len());
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut best = None;
+    let mut cursor = 0;
+    while order.len() < to_emit {
+        // If nothing in the cache has triangles left, continue with the next
+        // triangle in the input order
+        let tri_idx = match best {
+            Some(idx) => idx,
+            None => {
+                while emitted[cursor] {
+                    cursor += 1;
+                }
+                cursor
+            }
+        };
+
+        // Emit the triangle
+        emitted[tri_idx] = true;
+        order.push(tri_idx as u32);
+        let tri = triangles[tri_idx];
+        for &idx in &tri {
+            let idx = idx as usize;
+            valence[idx] -= 1;
+
+            // Remove the triangle from the vertex's remaining triangles
+            let start = offsets[idx];
+            let end = start + valence[idx] as usize;
+            let pos = adjacency[start..=end].iter()
+                .position(|&x| x as usize == tri_idx).unwrap();
+            adjacency.swap(start + pos, end);
+        }
+
+        // Move the triangle's verticies to the front of the cache
+        let old_cache = std::mem::take(&mut cache);
+        cache.extend_from_slice(&tri);
+        cache.extend(old_cache.iter().filter(|x| !tri.contains(x)));
+
+        // Rescore everything in the cache, and anything which fell out of it
+        for (pos, &idx) in cache.iter().enumerate() {
+            cache_pos[idx as usize] = if pos < CACHE_SIZE {
+                Some(pos)
+            } else {
+                None
+            };
+        }
+        for &idx in &cache {
+            let idx = idx as usize;
+            let new_score = vertex_score(cache_pos[idx], valence[idx]);
+            let delta = new_score - score[idx];
+            score[idx] = new_score;
+
+            let start = offsets[idx];
+            for &adj in &adjacency[start..start + valence[idx] as usize] {
+                tri_score[adj as usize] += delta;
+            }
+        }
+
+        // Only pick the next triangle once all its verticies are rescored
+        best = None;
+        let mut best_score = -1.;
+        for &idx in &cache {
+            let idx = idx as usize;
+            let start = offsets[idx];
+            for &adj in &adjacency[start..start + valence[idx] as usize] {
+                let adj = adj as usize;
+                if tri_score[adj] > best_score {
+                    best_score = tri_score[adj];
+                    best = Some(adj);
+                }
+            }
+        }
+        cache.truncate(CACHE_SIZE);
+    }
+
+    order.extend((0..triangles.len() as u32)
+        .filter(|&x| is_degenerate(triangles[x as usize])));
+    order
+}
+
+/// Reorder the triangles and verticies of `mesh` for vertex cache locality.
+/// Verticies which no triangle uses are dropped.
+pub fn optimize(mesh: &mut Mesh) {
+    let order = forsyth_order(&mesh.triangles, mesh.vertices.len());
+    let mut triangles: Vec<[u32; 3]> = order.iter()
+        .map(|&x| mesh.triangles[x as usize]).collect();
+
+    // Number the verticies in the order they are first used
+    let mut remap = vec![u32::MAX; mesh.vertices.len()];
+    let mut vertices: Vec<Vertex> = Vec::with_capacity(mesh.vertices.len());
+    for tri in &mut triangles {
+        for idx in tri.iter_mut() {
+            if remap[*idx as usize] == u32::MAX {
+                remap[*idx as usize] = vertices.len() as u32;
+                vertices.push(mesh.vertices[*idx as usize]);
+            }
+            *idx = remap[*idx as usize];
+        }
+    }
+
+    mesh.vertices  = vertices;
+    mesh.triangles = triangles;
+}
+
+/// Get the average cache miss ratio of `triangles`, the number of verticies
+/// transformed per triangle, with a FIFO cache of `cache_size` verticies.
+/// Lower is better, 0.5 is the best possible on a large grid and 3 the worst.
+pub fn acmr(triangles: &[[u32; 3]], cache_size: usize) -> f32 {
+    if triangles.is_empty() {
+        return 0.;
+    }
+
+    let mut cache = std::collections::VecDeque::with_capacity(cache_size);
+    let mut misses = 0usize;
+    for tri in triangles {
+        for idx in tri {
+            if !cache.contains(idx) {
+                misses += 1;
+                if cache.len() == cache_size {
+                    cache.pop_front();
+                }
+                cache.push_back(*idx);
+            }
+        }
+    }
+
+    misses as f32 / triangles.len() as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A grid of `width` by `width` verticies with its triangles in a
+    /// scrambled order
+    fn scrambled_grid(width: u32) -> Mesh {
+        let vertices = (0..width * width)
+            .map(|x| Vertex((x % width) as f32, 0., (x / width) as f32))
+            .collect();
+        let mut triangles = Vec::new();
+        for z in 0..width - 1 {
+            for x in 0..width - 1 {
+                let a = z * width + x;
+                triangles.push([a, a + width, a + 1]);
+                triangles.push([a + 1, a + width, a + width + 1]);
+            }
+        }
+
+        // Deterministic shuffle
+        let mut seed = 0x1234_5678u64;
+        for ii in (1..triangles.len()).rev() {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            triangles.swap(ii, (seed >> 33) as usize % (ii + 1));
+        }
+
+        Mesh { vertices, triangles }
+    }
+
+    /// Get the triangles of `mesh` as vertex positions, sorted
+    fn resolved(mesh: &Mesh) -> Vec<[Vertex; 3]> {
+        let mut ret: Vec<[Vertex; 3]> = mesh.triangles.iter().map(|x| [
+            mesh.vertices[x[0] as usize],
+            mesh.vertices[x[1] as usize],
+            mesh.vertices[x[2] as usize],
+        ]).collect();
+        ret.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        ret
+    }
+
+    #[test]
+    fn optimize_keeps_triangles_and_improves_locality() {
+        let mut mesh = scrambled_grid(64);
+        let before = acmr(&mesh.triangles, 16);
+
+        let expected = resolved(&mesh);
+        optimize(&mut mesh);
+        let after = acmr(&mesh.triangles, 16);
+
+        assert_eq!(resolved(&mesh), expected);
+        assert!(after < 1.0 && after < before / 2.,
+                "ACMR {} before, {} after", before, after);
+    }
+
+    #[test]
+    fn degenerate_triangles_go_last() {
+        let mut mesh = scrambled_grid(8);
+        mesh.triangles.insert(10, [3, 3, 4]);
+        mesh.triangles.insert(40, [9, 10, 9]);
+        mesh.triangles.push([5, 5, 5]);
+        let expected = resolved(&mesh);
+        optimize(&mut mesh);
+
+        assert_eq!(resolved(&mesh), expected);
+        let degenerate = mesh.triangles.iter()
+            .map(|&x| is_degenerate(x)).collect::<Vec<_>>();
+        let first = degenerate.iter().position(|&x| x).unwrap();
+        assert_eq!(first, mesh.triangles.len() - 3);
+        assert!(degenerate[first..].iter().all(|&x| x));
+    }
+}
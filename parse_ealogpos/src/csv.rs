@@ -0,0 +1,151 @@
+//! Comma separated position logs
+//!
+//! One position per line with the fields `time,x,y,z,map,angle`. An optional
+//! header line names the columns, in which case they can be in any order and
+//! `z` and `angle` can be left out. A first line with anything other than
+//! these names (or `map_id`) is a record rather than a header. Blank lines
+//! and lines starting with `#` are skipped.
+
+use std::io::{self, Write};
+
+use crate::{Position, fields};
+use crate::error::{Strictness, ParseError, ParseErrorKind, Collector};
+
+/// Parse all positions in the CSV `data`. Bad records are handled according
+/// to `strictness`, the errors for the ones which were skipped are returned
+/// along with the positions if it is `Strictness::Collect`.
+pub fn parse(data: &str, strictness: Strictness)
+        -> Result<(Vec<Position>, Vec<ParseError>), ParseError> {
+    let mut positions = Vec::new();
+    let mut errors    = Collector::new(data, strictness);
+
+    // Columns default to the order we write them in
+    let mut columns: Option<Vec<String>> = None;
+
+    let mut offset = 0;
+    for line in data.split_inclusive('\n') {
+        let line_offset = offset;
+        offset += line.len();
+
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let values: Vec<&str> = line.split(',').map(|x| x.trim()).collect();
+
+        // The first line is a header if it's all field names, otherwise a
+        // malformed first record would silently be taken as one
+        if columns.is_none() {
+            let names: Vec<String> = values.iter()
+                .map(|x| x.trim_matches('"').to_ascii_lowercase()).collect();
+            if names.iter().all(|x| {
+                fields::NAMES.contains(&x.as_str()) || x == "map_id"
+            }) {
+                columns = Some(names);
+                continue;
+            }
+            columns = Some(fields::NAMES.iter().map(|&x| x.into()).collect());
+        }
+        let columns = columns.as_ref().unwrap();
+
+        if values.len() > columns.len() {
+            errors.bad_record(line_offset, ParseErrorKind::TrailingData(
+                values[columns.len()..].join(",")))?;
+            continue;
+        }
+
+        let result = fields::position(|name| {
+            let idx = columns.iter().position(|x| x == name)?;
+            let value = values.get(idx).filter(|x| !x.is_empty())?;
+            Some(value.parse().map_err(|_| value.to_string()))
+        });
+        match result {
+            Ok(position) => positions.push(position),
+            Err(kind) => errors.bad_record(line_offset, kind)?,
+        }
+    }
+
+    Ok((positions, errors.errors))
+}
+
+/// Write `positions` as CSV with a header
+pub fn write<W: Write>(mut out: W, positions: &[Position]) -> io::Result<()> {
+    write!(out, "{}\n", fields::NAMES.join(","))?;
+    for pos in positions {
+        write!(out, "{},{},{},{},{},{}\n",
+               pos.time, pos.x, pos.y, pos.z, pos.map_id, pos.angle)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headerless_csv_parses() {
+        let data = "# time,x,y,z,map,angle\n\
+                    100,1.5,-2,3,1,0.25\r\n\
+                    \n\
+                    101,1,2,3,x,0\n\
+                    102,1,2\n";
+        let (positions, errors) = parse(data, Strictness::Collect).unwrap();
+        assert_eq!(positions.len(), 1);
+        let pos = positions[0];
+        assert_eq!((pos.time, pos.x, pos.y, pos.z, pos.map_id, pos.angle),
+                   (100., 1.5, -2., 3., 1, 0.25));
+
+        assert_eq!(errors, vec![
+            ParseError {
+                offset: data.find("101").unwrap(),
+                line:   4,
+                kind:   ParseErrorKind::InvalidField {
+                    field: "map",
+                    value: "x".into(),
+                },
+            },
+            ParseError {
+                offset: data.find("102").unwrap(),
+                line:   5,
+                kind:   ParseErrorKind::MissingField("map"),
+            },
+        ]);
+    }
+
+    #[test]
+    fn header_names_the_columns() {
+        let data = "map_id, Y, X, \"time\"\n0, 2, 1, 100\n";
+        let (positions, _) = parse(data, Strictness::Strict).unwrap();
+        let pos = positions[0];
+        assert_eq!((pos.time, pos.x, pos.y, pos.z, pos.map_id, pos.angle),
+                   (100., 1., 2., 0., 0, 0.));
+    }
+
+    #[test]
+    fn bad_first_records_are_not_headers() {
+        let data = "100,1,y,3,0,0\n101,1,2,3,0,0\n";
+        let (positions, errors) = parse(data, Strictness::Collect).unwrap();
+        assert_eq!(positions.len(), 1);
+        assert_eq!(errors, vec![ParseError {
+            offset: 0,
+            line:   1,
+            kind:   ParseErrorKind::InvalidField {
+                field: "y",
+                value: "y".into(),
+            },
+        }]);
+
+        let err = parse("time,x,y,speed\n1,2,3,4\n", Strictness::Strict)
+            .unwrap_err();
+        assert_eq!((err.line, err.kind), (1, ParseErrorKind::InvalidField {
+            field: "time",
+            value: "time".into(),
+        }));
+    }
+
+    #[test]
+    fn trailing_fields_are_errors() {
+        let err = parse("1,2,3,4,5,6,7\n", Strictness::Strict).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::TrailingData("7".into()));
+    }
+}
@@ -0,0 +1,196 @@
+//! Errors shared by all of the position log formats
+
+use std::io;
+use std::fmt;
+
+/// Deepest nesting of tables, objects or arrays the parsers accept, deeper
+/// data is a syntax error rather than a stack overflow
+pub(crate) const MAX_DEPTH: usize = 128;
+
+/// How bad records are handled while parsing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strictness {
+    /// Fail on the first bad record
+    Strict,
+
+    /// Skip bad records, returning an error for each of them
+    Collect,
+
+    /// Skip bad records silently
+    Skip,
+}
+
+impl Default for Strictness {
+    /// Collect by default, a single bad record shouldn't lose a whole
+    /// recording but it shouldn't go unnoticed either
+    fn default() -> Self {
+        Strictness::Collect
+    }
+}
+
+/// The different problems which can be found in a record
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseErrorKind {
+    /// The record has no closing quote before the end of the line
+    Unterminated,
+
+    /// The record ended before field `0`
+    MissingField(&'static str),
+
+    /// Field `field` is not a valid number
+    InvalidField {
+        field: &'static str,
+        value: String,
+    },
+
+    /// Field `0` is infinite or not a number
+    NonFinite(&'static str),
+
+    /// There is more data after the last field
+    TrailingData(String),
+
+    /// The file doesn't follow the syntax of its format, `0` describes what
+    /// was expected. In formats without one record per line these are never
+    /// skipped, as there is no telling where the next record starts.
+    Syntax(String),
+}
+
+/// An error in a record, along with where it is
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Byte offset into the data of the start of the problem
+    pub offset: usize,
+
+    /// One-based line number of `offset`
+    pub line: usize,
+
+    /// What went wrong
+    pub kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {} (byte {}): ", self.line, self.offset)?;
+        match &self.kind {
+            ParseErrorKind::Unterminated =>
+                write!(f, "Record has no closing quote"),
+            ParseErrorKind::MissingField(field) =>
+                write!(f, "Record is missing the {} field", field),
+            ParseErrorKind::InvalidField { field, value } =>
+                write!(f, "Invalid {} {:?}", field, value),
+            ParseErrorKind::NonFinite(field) =>
+                write!(f, "The {} field is not finite", field),
+            ParseErrorKind::TrailingData(data) =>
+                write!(f, "Unexpected data {:?} after the last field", data),
+            ParseErrorKind::Syntax(expected) =>
+                write!(f, "Expected {}", expected),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// An error from loading positions
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the file failed
+    Io(io::Error),
+
+    /// A record was malformed
+    Parse(ParseError),
+
+    /// The format of the file couldn't be determined
+    UnknownFormat,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err)       => write!(f, "{}", err),
+            Error::Parse(err)    => write!(f, "{}", err),
+            Error::UnknownFormat => write!(f, "Unknown position log format"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err)       => Some(err),
+            Error::Parse(err)    => Some(err),
+            Error::UnknownFormat => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+/// Creates located errors for offsets into `data`, and handles bad records
+/// according to a `Strictness`
+pub(crate) struct Collector<'a> {
+    /// The data being parsed
+    data: &'a str,
+
+    /// How bad records are handled
+    strictness: Strictness,
+
+    /// Line number tracking, `line` is the line `line_offset` is on
+    line: usize,
+    line_offset: usize,
+
+    /// Errors for the bad records which were skipped
+    pub errors: Vec<ParseError>,
+}
+
+impl<'a> Collector<'a> {
+    /// Create a collector for errors in `data`
+    pub fn new(data: &'a str, strictness: Strictness) -> Self {
+        Collector {
+            data,
+            strictness,
+            line: 1,
+            line_offset: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Create an error at byte `offset` of the data
+    pub fn error(&mut self, offset: usize, kind: ParseErrorKind)
+            -> ParseError {
+        // Offsets mostly increase, so only count the lines since the last
+        // error
+        if offset < self.line_offset {
+            self.line = 1;
+            self.line_offset = 0;
+        }
+        self.line += self.data[self.line_offset..offset].matches('\n').count();
+        self.line_offset = offset;
+
+        ParseError { offset, line: self.line, kind }
+    }
+
+    /// Report a bad record at byte `offset` of the data, which is an error
+    /// if parsing is strict
+    pub fn bad_record(&mut self, offset: usize, kind: ParseErrorKind)
+            -> Result<(), ParseError> {
+        match self.strictness {
+            Strictness::Skip => Ok(()),
+            Strictness::Collect => {
+                let err = self.error(offset, kind);
+                self.errors.push(err);
+                Ok(())
+            }
+            Strictness::Strict => Err(self.error(offset, kind)),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Building positions from named fields, shared by the formats which name
+//! their fields
+
+use crate::Position;
+use crate::error::ParseErrorKind;
+
+/// The fields of a position in the order they are written. `map` is also
+/// accepted as `map_id` when reading.
+pub(crate) const NAMES: [&str; 6] = ["time", "x", "y", "z", "map", "angle"];
+
+/// A field value as found in a record, either a number or the text of
+/// whatever else was there
+pub(crate) type Value = Result<f64, String>;
+
+/// Build a position from the fields `lookup` returns by name. `z` and
+/// `angle` default to 0 if missing, the rest are required, and all of them
+/// must be finite.
+pub(crate) fn position<F>(mut lookup: F) -> Result<Position, ParseErrorKind>
+        where F: FnMut(&str) -> Option<Value> {
+    let mut values = [0f64; 6];
+    for (ii, &name) in NAMES.iter().enumerate() {
+        let value = match lookup(name) {
+            None if name == "map" => lookup("map_id"),
+            value => value,
+        };
+
+        values[ii] = match value {
+            Some(Ok(value)) if !value.is_finite() => {
+                return Err(ParseErrorKind::NonFinite(name));
+            }
+            Some(Ok(value)) => value,
+            Some(Err(value)) => return Err(ParseErrorKind::InvalidField {
+                field: name,
+                value,
+            }),
+            None if name == "z" || name == "angle" => 0.,
+            None => return Err(ParseErrorKind::MissingField(name)),
+        };
+    }
+
+    let [time, x, y, z, map_id, angle] = values;
+    if map_id.fract() != 0. || !(0. ..=u32::MAX as f64).contains(&map_id) {
+        return Err(ParseErrorKind::InvalidField {
+            field: "map",
+            value: map_id.to_string(),
+        });
+    }
+
+    Ok(Position { time, x, y, z, map_id: map_id as u32, angle })
+}
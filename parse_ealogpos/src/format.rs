@@ -0,0 +1,184 @@
+//! The supported position log formats, and picking between them
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{Position, Positions, Strictness, ParseError, Error};
+use crate::{lua, lua_table, csv, jsonl};
+
+/// A position log format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// The position logging addon's saved variables, positions encoded as
+    /// `LOCATION237945_` strings
+    Addon,
+
+    /// SavedVariables with positions stored as native LUA tables
+    LuaTable,
+
+    /// Comma separated values
+    Csv,
+
+    /// One JSON object per line
+    JsonLines,
+}
+
+/// All formats, in the order they are listed to users
+pub const FORMATS: [Format; 4] =
+    [Format::Addon, Format::LuaTable, Format::Csv, Format::JsonLines];
+
+impl Format {
+    /// Name of the format, as accepted by `from_str`
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Addon     => "addon",
+            Format::LuaTable  => "lua",
+            Format::Csv       => "csv",
+            Format::JsonLines => "jsonl",
+        }
+    }
+
+    /// Guess the format from the extension of `path`. `.lua` files could be
+    /// either LUA format, so the contents are needed to tell them apart.
+    /// `.json` files are expected to hold a single document, which JSON Lines
+    /// isn't.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "csv"             => Some(Format::Csv),
+            "jsonl" | "ndjson" => Some(Format::JsonLines),
+            _ => None,
+        }
+    }
+
+    /// Guess the format from the contents of a file
+    pub fn sniff(data: &str) -> Option<Self> {
+        if data.contains(lua::MAGIC) {
+            return Some(Format::Addon);
+        }
+
+        // Go by the first line with something on it
+        let line = data.lines().map(|x| x.trim())
+            .find(|x| !x.is_empty() && !x.starts_with('#'))?;
+        if line.starts_with('{') {
+            Some(Format::JsonLines)
+        } else if line.starts_with("--") || line.contains('=') {
+            Some(Format::LuaTable)
+        } else if line.contains(',') {
+            Some(Format::Csv)
+        } else {
+            None
+        }
+    }
+
+    /// Pick the format of the file at `path` with contents `data`, going by
+    /// the extension and falling back to the contents
+    pub fn detect(path: &Path, data: &str) -> Option<Self> {
+        Self::from_extension(path).or_else(|| Self::sniff(data))
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        FORMATS.iter().copied().find(|x| x.name() == name)
+            .ok_or_else(|| format!("Unknown format {:?}", name))
+    }
+}
+
+impl Positions {
+    /// Parse the positions in `data`, which is in `format`. Bad records are
+    /// handled according to `strictness`, the errors for the ones which were
+    /// skipped are returned with the positions.
+    pub fn parse(data: &str, format: Format, strictness: Strictness)
+            -> Result<(Self, Vec<ParseError>), ParseError> {
+        let (positions, errors) = match format {
+            Format::Addon     => lua::parse(data, strictness)?,
+            Format::LuaTable  => lua_table::parse(data, strictness)?,
+            Format::Csv       => csv::parse(data, strictness)?,
+            Format::JsonLines => jsonl::parse(data, strictness)?,
+        };
+        Ok((Positions { positions }, errors))
+    }
+
+    /// Load the positions from the file at `path` in any format, detecting
+    /// it with `Format::detect`. See `parse`.
+    pub fn load<P: AsRef<Path>>(path: P, strictness: Strictness)
+            -> Result<(Self, Vec<ParseError>), Error> {
+        let path = path.as_ref();
+        let data = std::fs::read_to_string(path)?;
+        let format = Format::detect(path, &data).ok_or(Error::UnknownFormat)?;
+        Ok(Self::parse(&data, format, strictness)?)
+    }
+
+    /// Write the positions to `out` in `format`
+    pub fn write<W: Write>(&self, out: W, format: Format) -> io::Result<()> {
+        let positions: &[Position] = &self.positions;
+        match format {
+            Format::Addon     => lua::write(out, positions),
+            Format::LuaTable  => lua_table::write(out, positions),
+            Format::Csv       => csv::write(out, positions),
+            Format::JsonLines => jsonl::write(out, positions),
+        }
+    }
+
+    /// Save the positions to a file at `path` in `format`
+    pub fn save<P: AsRef<Path>>(&self, path: P, format: Format)
+            -> io::Result<()> {
+        let mut out = io::BufWriter::new(std::fs::File::create(path)?);
+        self.write(&mut out, format)?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_are_detected() {
+        let detect = |name: &str, data: &str| {
+            Format::detect(Path::new(name), data)
+        };
+        assert_eq!(detect("a.CSV", "{}"), Some(Format::Csv));
+        assert_eq!(detect("a.jsonl", ""), Some(Format::JsonLines));
+        assert_eq!(Format::from_extension(Path::new("a.json")), None);
+        assert_eq!(detect("a.json", "{\"time\": 1}"), Some(Format::JsonLines));
+        assert_eq!(detect("a.lua", "X = { \"LOCATION237945_1_2\" }"),
+                   Some(Format::Addon));
+        assert_eq!(detect("a.lua", "X = { { 1, 2, 3, 4, 5 } }"),
+                   Some(Format::LuaTable));
+        assert_eq!(detect("a.txt", "\n  {\"time\": 1}"),
+                   Some(Format::JsonLines));
+        assert_eq!(detect("a", "# comment\ntime,x,y\n"), Some(Format::Csv));
+        assert_eq!(detect("a", "hello"), None);
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let positions = Positions { positions: vec![
+            Position {
+                time: 81234.5, x: -8913.25, y: 554.5, z: 93.75, map_id: 0,
+                angle: 3.25,
+            },
+            Position {
+                time: 81234.75, x: 1e-7, y: -0., z: 1e20, map_id: 530,
+                angle: 0.1,
+            },
+        ]};
+
+        for &format in &FORMATS {
+            let mut data = Vec::new();
+            positions.write(&mut data, format).unwrap();
+            let data = String::from_utf8(data).unwrap();
+
+            assert_eq!(Format::sniff(&data), Some(format), "{}", data);
+            let (parsed, errors) =
+                Positions::parse(&data, format, Strictness::Strict).unwrap();
+            assert!(errors.is_empty());
+            assert_eq!(parsed.positions, positions.positions, "{}", data);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Sampling recordings at arbitrary times
+//!
+//! Recordings are assumed to be sorted by time. Positions between samples are
+//! interpolated either linearly or with a Catmull-Rom spline through the
+//! samples, which uses the sample times so uneven sample rates don't cause
+//! overshoot. Angles take the shortest way around.
+
+use crate::{Position, Positions};
+
+/// Number of degrees in a full turn, the period of `Position::angle`
+const FULL_TURN: f64 = 360.;
+
+/// Most positions a recording is resampled to, about 800 MiB of them
+const MAX_SAMPLES: f64 = (1u64 << 24) as f64;
+
+/// How positions between samples are computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Straight lines between samples
+    Linear,
+
+    /// A smooth curve through the samples
+    CatmullRom,
+}
+
+/// Get the signed shortest angle to turn from `from` to `to`
+fn angle_delta(from: f64, to: f64) -> f64 {
+    let delta = (to - from).rem_euclid(FULL_TURN);
+    if delta > FULL_TURN / 2. {
+        delta - FULL_TURN
+    } else {
+        delta
+    }
+}
+
+/// Evaluate a cubic Hermite curve from `p1` to `p2` with tangents `m1` and
+/// `m2` (per unit of `u`) at `u` from 0 to 1
+fn hermite(p1: f64, p2: f64, m1: f64, m2: f64, u: f64) -> f64 {
+    let u2 = u * u;
+    let u3 = u2 * u;
+    (2. * u3 - 3. * u2 + 1.) * p1 + (u3 - 2. * u2 + u) * m1 +
+        (-2. * u3 + 3. * u2) * p2 + (u3 - u2) * m2
+}
+
+/// Interpolate between `p1` and `p2` at times `t1` and `t2` at `time`, using
+/// the neighbouring samples `p0` and `p3` at `t0` and `t3` for the tangents
+#[allow(clippy::too_many_arguments)]
+fn catmull_rom(p0: f64, p1: f64, p2: f64, p3: f64,
+               t0: f64, t1: f64, t2: f64, t3: f64, time: f64) -> f64 {
+    let span = t2 - t1;
+    let slope = |a: f64, b: f64, ta: f64, tb: f64| {
+        if tb > ta { (b - a) / (tb - ta) } else { 0. }
+    };
+    let m1 = slope(p0, p2, t0, t2) * span;
+    let m2 = slope(p1, p3, t1, t3) * span;
+    hermite(p1, p2, m1, m2, (time - t1) / span)
+}
+
+impl Positions {
+    /// Get the position at `time`, interpolated with `mode`. Returns `None`
+    /// if `time` is outside of the recording. Between samples on different
+    /// maps the earlier sample is returned as-is.
+    pub fn sample_at(&self, time: f64, mode: Interpolation)
+            -> Option<Position> {
+        let positions = &self.positions;
+        let first = positions.first()?;
+        let last  = positions.last()?;
+        if !(first.time..=last.time).contains(&time) {
+            return None;
+        }
+
+        // Find the samples either side of `time`
+        let idx = positions.partition_point(|x| x.time <= time);
+        if idx == positions.len() {
+            return Some(*last);
+        }
+        let (a, b) = (&positions[idx - 1], &positions[idx]);
+        if a.map_id != b.map_id || b.time <= a.time {
+            return Some(*a);
+        }
+
+        let mut ret = Position { time, ..*a };
+        match mode {
+            Interpolation::Linear => {
+                let u = (time - a.time) / (b.time - a.time);
+                ret.x = a.x + (b.x - a.x) * u;
+                ret.y = a.y + (b.y - a.y) * u;
+                ret.z = a.z + (b.z - a.z) * u;
+                ret.angle = a.angle + angle_delta(a.angle, b.angle) * u;
+            }
+            Interpolation::CatmullRom => {
+                // The ends have no neighbour, and neither do samples next to
+                // a map change, so use the sample itself
+                let before = positions.get(idx.wrapping_sub(2))
+                    .filter(|x| x.map_id == a.map_id).unwrap_or(a);
+                let after = positions.get(idx + 1)
+                    .filter(|x| x.map_id == b.map_id).unwrap_or(b);
+
+                let interp = |get: fn(&Position) -> f64| {
+                    catmull_rom(get(before), get(a), get(b), get(after),
+                                before.time, a.time, b.time, after.time,
+                                time)
+                };
+                ret.x = interp(|x| x.x);
+                ret.y = interp(|x| x.y);
+                ret.z = interp(|x| x.z);
+
+                // Unwrap the angles around `a` so they are continuous
+                let p0 = a.angle - angle_delta(before.angle, a.angle);
+                let p2 = a.angle + angle_delta(a.angle, b.angle);
+                let p3 = p2 + angle_delta(b.angle, after.angle);
+                ret.angle = catmull_rom(p0, a.angle, p2, p3,
+                                        before.time, a.time, b.time,
+                                        after.time, time);
+            }
+        }
+        ret.angle = ret.angle.rem_euclid(FULL_TURN);
+
+        Some(ret)
+    }
+
+    /// Resample the recording at `rate` samples per second, starting at the
+    /// first sample. Returns `None` if `rate` isn't a finite positive number
+    /// or would give more than `MAX_SAMPLES` positions.
+    pub fn resample(&self, rate: f64, mode: Interpolation)
+            -> Option<Positions> {
+        if !rate.is_finite() || rate <= 0. {
+            return None;
+        }
+
+        let mut positions = Vec::new();
+        if let (Some(first), Some(last)) =
+                (self.positions.first(), self.positions.last()) {
+            let count = ((last.time - first.time) * rate).floor();
+            if !(0. ..MAX_SAMPLES).contains(&count) {
+                return None;
+            }
+            positions.extend((0..count as usize + 1).filter_map(|ii| {
+                self.sample_at(first.time + ii as f64 / rate, mode)
+            }));
+        }
+
+        Some(Positions { positions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a position on map 0
+    fn pos(time: f64, x: f64, y: f64, z: f64, angle: f64) -> Position {
+        Position { time, x, y, z, map_id: 0, angle }
+    }
+
+    #[test]
+    fn linear_interpolates_between_samples() {
+        let positions = Positions { positions: vec![
+            pos(10., 0., 0., 0., 350.),
+            pos(12., 2., 4., -2., 10.),
+            pos(13., 2., 4., -2., 10.),
+        ]};
+
+        let mid = positions.sample_at(11., Interpolation::Linear).unwrap();
+        assert_eq!((mid.time, mid.x, mid.y, mid.z, mid.angle),
+                   (11., 1., 2., -1., 0.));
+        let quarter = positions.sample_at(10.5, Interpolation::Linear)
+            .unwrap();
+        assert_eq!(quarter.angle, 355.);
+
+        assert_eq!(positions.sample_at(13., Interpolation::Linear).unwrap(),
+                   positions.positions[2]);
+        assert!(positions.sample_at(9.9, Interpolation::Linear).is_none());
+        assert!(positions.sample_at(13.1, Interpolation::Linear).is_none());
+    }
+
+    #[test]
+    fn catmull_rom_is_smooth_and_exact_on_lines() {
+        // Constant velocity at uneven sample times stays on the line
+        let positions = Positions { positions: [0., 0.5, 2., 2.25, 4.]
+            .iter().map(|&t| pos(t, 3. * t, -t, 1., 90. * t)).collect() };
+        for ii in 0..=40 {
+            let time = ii as f64 / 10.;
+            let sample = positions.sample_at(time, Interpolation::CatmullRom)
+                .unwrap();
+            assert!((sample.x - 3. * time).abs() < 1e-9, "x at {}", time);
+            assert!((sample.y + time).abs() < 1e-9, "y at {}", time);
+            assert!(angle_delta(sample.angle, 90. * time).abs() < 1e-9,
+                    "angle at {}", time);
+        }
+    }
+
+    #[test]
+    fn map_changes_are_not_interpolated() {
+        let mut positions = Positions { positions: vec![
+            pos(0., 0., 0., 0., 0.),
+            pos(1., 10., 0., 0., 0.),
+        ]};
+        positions.positions[1].map_id = 1;
+        for &mode in &[Interpolation::Linear, Interpolation::CatmullRom] {
+            assert_eq!(positions.sample_at(0.5, mode).unwrap(),
+                       positions.positions[0]);
+        }
+    }
+
+    #[test]
+    fn resampling_uses_a_fixed_rate() {
+        let positions = Positions { positions: vec![
+            pos(100., 0., 0., 0., 0.),
+            pos(100.3, 3., 0., 0., 0.),
+            pos(101.05, 3., 7.5, 0., 0.),
+        ]};
+
+        let resampled = positions.resample(4., Interpolation::Linear)
+            .unwrap();
+        let expected = [
+            (100.,   0.,  0.),
+            (100.25, 2.5, 0.),
+            (100.5,  3.,  2.),
+            (100.75, 3.,  4.5),
+            (101.,   3.,  7.),
+        ];
+        assert_eq!(resampled.positions.len(), expected.len());
+        for (pos, &(time, x, y)) in resampled.positions.iter().zip(&expected) {
+            assert!((pos.time - time).abs() < 1e-9 &&
+                    (pos.x - x).abs() < 1e-9 && (pos.y - y).abs() < 1e-9,
+                    "{:?} != {:?}", pos, (time, x, y));
+        }
+        assert!(Positions { positions: Vec::new() }
+                .resample(1., Interpolation::Linear).unwrap()
+                .positions.is_empty());
+
+        for &rate in &[0., -1., f64::NAN, f64::INFINITY, 1e300] {
+            assert!(positions.resample(rate, Interpolation::Linear).is_none(),
+                    "rate {}", rate);
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! JSON Lines position logs
+//!
+//! One JSON object per line, eg.
+//!
+//! ```text
+//! {"time": 81234.5, "x": -8913.2, "y": 554.6, "z": 93.7, "map": 0, "angle": 178.5}
+//! ```
+//!
+//! The map can also be named `map_id`, `z` and `angle` can be left out, and
+//! other keys are ignored. Blank lines are skipped.
+
+use std::io::{self, Write};
+
+use crate::{Position, fields};
+use crate::error::{Strictness, ParseError, ParseErrorKind, Collector};
+use crate::error::MAX_DEPTH;
+
+/// A parsed JSON value
+#[derive(Debug, Clone, PartialEq)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// A recursive descent JSON parser over a single line
+struct Parser<'a> {
+    /// The line being parsed
+    data: &'a [u8],
+
+    /// Current byte offset into `data`
+    pos: usize,
+
+    /// Number of objects and arrays the parser is inside of
+    depth: usize,
+}
+
+impl Parser<'_> {
+    /// Skip any whitespace
+    fn skip_whitespace(&mut self) {
+        while self.data.get(self.pos).map(|x| x.is_ascii_whitespace())
+                == Some(true) {
+            self.pos += 1;
+        }
+    }
+
+    /// Consume `byte` if it's next, after any whitespace
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        if self.data.get(self.pos) == Some(&byte) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consume `byte`, which must be next
+    fn expect(&mut self, byte: u8) -> Result<(), (usize, String)> {
+        if self.eat(byte) {
+            Ok(())
+        } else {
+            Err((self.pos, format!("`{}`", byte as char)))
+        }
+    }
+
+    /// Parse a string, the opening quote has to be next
+    fn string(&mut self) -> Result<String, (usize, String)> {
+        self.expect(b'"')?;
+        let mut ret = Vec::new();
+        loop {
+            let byte = *self.data.get(self.pos)
+                .ok_or((self.pos, "closing `\"`".into()))?;
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.data.get(self.pos).copied();
+                    self.pos += 1;
+                    let chr = match escape {
+                        Some(b'"')  => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/')  => '/',
+                        Some(b'b')  => '\x08',
+                        Some(b'f')  => '\x0c',
+                        Some(b'n')  => '\n',
+                        Some(b'r')  => '\r',
+                        Some(b't')  => '\t',
+                        Some(b'u')  => {
+                            let hex = self.data.get(self.pos..self.pos + 4)
+                                .and_then(|x| std::str::from_utf8(x).ok())
+                                .and_then(|x| u32::from_str_radix(x, 16).ok())
+                                .ok_or((self.pos, "4 hex digits".into()))?;
+                            self.pos += 4;
+                            char::from_u32(hex).unwrap_or('\u{fffd}')
+                        }
+                        _ => return Err((self.pos - 1, "an escape".into())),
+                    };
+                    ret.extend_from_slice(
+                        chr.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                _ => ret.push(byte),
+            }
+        }
+
+        // The input is a `str` and we only split it on ASCII
+        Ok(String::from_utf8(ret).unwrap())
+    }
+
+    /// Parse any value
+    fn value(&mut self) -> Result<Json, (usize, String)> {
+        self.skip_whitespace();
+        let start = self.pos;
+        match self.data.get(self.pos) {
+            Some(b'{') | Some(b'[') if self.depth == MAX_DEPTH => {
+                Err((start, format!("at most {} nested values", MAX_DEPTH)))
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                self.depth += 1;
+                let mut members = Vec::new();
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        self.expect(b':')?;
+                        members.push((key, self.value()?));
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Object(members))
+            }
+            Some(b'[') => {
+                self.pos += 1;
+                self.depth += 1;
+                let mut elements = Vec::new();
+                if !self.eat(b']') {
+                    loop {
+                        elements.push(self.value()?);
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Array(elements))
+            }
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            _ => {
+                // Numbers and literals run until a delimiter
+                while self.data.get(self.pos).map(|x| {
+                    !b",:]}".contains(x) && !x.is_ascii_whitespace()
+                }) == Some(true) {
+                    self.pos += 1;
+                }
+
+                let token = std::str::from_utf8(&self.data[start..self.pos])
+                    .unwrap();
+                match token {
+                    "null"  => Ok(Json::Null),
+                    "true"  => Ok(Json::Bool(true)),
+                    "false" => Ok(Json::Bool(false)),
+                    _ => token.parse().map(Json::Number)
+                        .map_err(|_| (start, "a value".into())),
+                }
+            }
+        }
+    }
+}
+
+/// Get the text of a JSON value, for error messages
+fn describe(value: &Json) -> String {
+    match value {
+        Json::Null       => "null".into(),
+        Json::Bool(val)  => val.to_string(),
+        Json::Number(n)  => n.to_string(),
+        Json::String(s)  => s.clone(),
+        Json::Array(_)   => "an array".into(),
+        Json::Object(_)  => "an object".into(),
+    }
+}
+
+/// Parse all positions in the JSON Lines `data`. Bad records are handled
+/// according to `strictness`, the errors for the ones which were skipped are
+/// returned along with the positions if it is `Strictness::Collect`.
+pub fn parse(data: &str, strictness: Strictness)
+        -> Result<(Vec<Position>, Vec<ParseError>), ParseError> {
+    let mut positions = Vec::new();
+    let mut errors    = Collector::new(data, strictness);
+
+    let mut offset = 0;
+    for line in data.split_inclusive('\n') {
+        let line_offset = offset;
+        offset += line.len();
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // Each line is a self contained value, so a syntax error only
+        // affects its own record
+        let mut parser = Parser { data: line.as_bytes(), pos: 0, depth: 0 };
+        let value = parser.value().and_then(|value| {
+            parser.skip_whitespace();
+            if parser.pos == line.len() {
+                Ok(value)
+            } else {
+                Err((parser.pos, "the end of the line".into()))
+            }
+        });
+        let members = match value {
+            Ok(Json::Object(members)) => members,
+            Ok(_) => {
+                errors.bad_record(line_offset,
+                    ParseErrorKind::Syntax("an object".into()))?;
+                continue;
+            }
+            Err((pos, expected)) => {
+                errors.bad_record(line_offset + pos,
+                    ParseErrorKind::Syntax(expected))?;
+                continue;
+            }
+        };
+
+        let result = fields::position(|name| {
+            match &members.iter().find(|x| x.0 == name)?.1 {
+                Json::Null => None,
+                Json::Number(val) => Some(Ok(*val)),
+                other => Some(Err(describe(other))),
+            }
+        });
+        match result {
+            Ok(position) => positions.push(position),
+            Err(kind) => errors.bad_record(line_offset, kind)?,
+        }
+    }
+
+    Ok((positions, errors.errors))
+}
+
+/// Write `positions` as JSON Lines
+pub fn write<W: Write>(mut out: W, positions: &[Position]) -> io::Result<()> {
+    for pos in positions {
+        write!(out, "{{\"time\":{},\"x\":{},\"y\":{},\"z\":{},\"map\":{},\
+                     \"angle\":{}}}\n",
+               pos.time, pos.x, pos.y, pos.z, pos.map_id, pos.angle)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn objects_parse() {
+        let data = "{\"time\": 100, \"x\": 1.5, \"y\": -2e1, \"map_id\": 1, \
+                    \"name\": \"a\\\"b\\u00e9\", \"extra\": [1, {}, null]}\n\
+                    \n\
+                    {\"time\": 101, \"x\": 1, \"y\": 2, \"map\": \"Azeroth\"}\n\
+                    {\"time\": 102, \"x\": 1,, \"y\": 2}\n\
+                    [1, 2]\n";
+        let (positions, errors) = parse(data, Strictness::Collect).unwrap();
+        assert_eq!(positions.len(), 1);
+        let pos = positions[0];
+        assert_eq!((pos.time, pos.x, pos.y, pos.z, pos.map_id, pos.angle),
+                   (100., 1.5, -20., 0., 1, 0.));
+
+        assert_eq!(errors.iter().map(|x| (x.line, x.kind.clone()))
+                   .collect::<Vec<_>>(), vec![
+            (3, ParseErrorKind::InvalidField {
+                field: "map",
+                value: "Azeroth".into(),
+            }),
+            (4, ParseErrorKind::Syntax("`\"`".into())),
+            (5, ParseErrorKind::Syntax("an object".into())),
+        ]);
+        assert_eq!(errors[1].offset, data.find(",, ").unwrap() + 1);
+    }
+
+    #[test]
+    fn non_finite_and_deeply_nested_lines_are_bad_records() {
+        let data = format!("{{\"time\": 1, \"x\": 1e999, \"y\": 2, \"map\": 0}}\n\
+                            {}\n\
+                            {{\"time\": 2, \"x\": 1, \"y\": 2, \"map\": 0}}\n",
+                           "[".repeat(100000));
+        let (positions, errors) = parse(&data, Strictness::Collect).unwrap();
+        assert_eq!(positions.len(), 1);
+        assert_eq!(errors.iter().map(|x| (x.line, x.kind.clone()))
+                   .collect::<Vec<_>>(), vec![
+            (1, ParseErrorKind::NonFinite("x")),
+            (2, ParseErrorKind::Syntax(
+                format!("at most {} nested values", MAX_DEPTH))),
+        ]);
+    }
+
+    #[test]
+    fn written_positions_parse() {
+        let positions = vec![Position {
+            time: 81234.5, x: -8913.25, y: 554.5, z: 93.75, map_id: 0,
+            angle: 3.25,
+        }];
+        let mut data = Vec::new();
+        write(&mut data, &positions).unwrap();
+
+        let data = String::from_utf8(data).unwrap();
+        let (parsed, _) = parse(&data, Strictness::Strict).unwrap();
+        assert_eq!(parsed.len(), 1);
+        assert_eq!((parsed[0].time, parsed[0].z, parsed[0].angle),
+                   (81234.5, 93.75, 3.25));
+    }
+}
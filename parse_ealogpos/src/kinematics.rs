@@ -0,0 +1,435 @@
+//! Motion analysis of recordings
+//!
+//! Velocities and accelerations are estimated with differences between
+//! neighbouring samples in the same segment, so nothing is computed across
+//! map changes, teleports or gaps. Jumps, falls and rides are then picked out
+//! of the vertical and horizontal speed profiles. The thresholds are tuned
+//! for a character running at 7 yards per second, so the detection is a
+//! heuristic and works best with a few samples per second.
+//!
+//! Headings are in degrees in the same convention as `Position::angle`, 0
+//! along the x axis and increasing towards the y axis.
+
+use crate::{Position, Positions, SegmentOptions};
+
+/// Vertical speed in yards per second above which a character is rising for
+/// a jump
+const JUMP_RISE_SPEED: f64 = 2.;
+
+/// Longest a jump can take from takeoff to landing, in seconds
+const JUMP_MAX_DURATION: f64 = 1.5;
+
+/// Highest a jump can go, in yards
+const JUMP_MAX_HEIGHT: f64 = 3.;
+
+/// Vertical speed in yards per second below which a character is dropping
+const FALL_SPEED: f64 = -2.;
+
+/// Shortest drop in yards which counts as a fall
+const FALL_MIN_HEIGHT: f64 = 5.;
+
+/// Horizontal speed in yards per second above which a character can't be
+/// running, so is on a mount, boat, zeppelin or taxi
+const RIDE_SPEED: f64 = 8.5;
+
+/// Shortest time above `RIDE_SPEED` which counts as a ride, in seconds
+const RIDE_MIN_DURATION: f64 = 3.;
+
+/// Horizontal speed below which the direction of travel is meaningless
+const MIN_HEADING_SPEED: f64 = 0.1;
+
+/// Motion at a single sample
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sample {
+    /// Velocity in yards per second along x, y and z
+    pub velocity: [f64; 3],
+
+    /// Acceleration in yards per second squared along x, y and z
+    pub acceleration: [f64; 3],
+
+    /// Horizontal speed in yards per second
+    pub speed: f64,
+
+    /// Direction of travel in degrees, `None` when not moving
+    pub heading: Option<f64>,
+
+    /// Rate the direction of travel is turning at in degrees per second,
+    /// positive towards the y axis
+    pub heading_change: f64,
+}
+
+/// The kinds of motion which are detected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// A short rise and drop
+    Jump,
+
+    /// A long drop
+    Fall,
+
+    /// A sustained speed faster than running
+    Ride,
+}
+
+impl EventKind {
+    /// Lowercase name of the kind
+    pub fn name(self) -> &'static str {
+        match self {
+            EventKind::Jump => "jump",
+            EventKind::Fall => "fall",
+            EventKind::Ride => "ride",
+        }
+    }
+}
+
+/// A detected jump, fall or ride
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    /// What happened
+    pub kind: EventKind,
+
+    /// Index of the first sample of the event
+    pub start: usize,
+
+    /// Index of the last sample of the event
+    pub end: usize,
+
+    /// Time from the first to the last sample in seconds
+    pub duration: f64,
+
+    /// Height gained for jumps, lost for falls, and the average speed for
+    /// rides
+    pub magnitude: f64,
+}
+
+/// Totals for a whole recording
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Summary {
+    /// Number of samples
+    pub samples: usize,
+
+    /// Number of continuous segments
+    pub segments: usize,
+
+    /// Time spent in segments, in seconds
+    pub duration: f64,
+
+    /// Distance travelled in segments, in yards
+    pub distance: f64,
+
+    /// `distance` over `duration`
+    pub average_speed: f64,
+
+    /// Highest horizontal speed
+    pub max_speed: f64,
+
+    /// Total height gained and lost
+    pub climb:   f64,
+    pub descent: f64,
+
+    /// Number of each kind of event
+    pub jumps: usize,
+    pub falls: usize,
+    pub rides: usize,
+
+    /// Time spent riding, in seconds
+    pub ride_time: f64,
+}
+
+/// The full analysis of a recording
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    /// Motion at each sample, in the same order as the positions
+    pub samples: Vec<Sample>,
+
+    /// Detected events, in order
+    pub events: Vec<Event>,
+
+    /// Totals for the recording
+    pub summary: Summary,
+}
+
+/// Estimate the time derivative of `values`, which are sampled at
+/// `positions`, with central differences inside and one-sided ones at the
+/// ends. `out` is called with each index and derivative.
+fn derivative<F>(positions: &[Position], values: &[[f64; 3]], mut out: F)
+        where F: FnMut(usize, [f64; 3]) {
+    if positions.len() < 2 {
+        for ii in 0..positions.len() {
+            out(ii, [0.; 3]);
+        }
+        return;
+    }
+
+    for ii in 0..positions.len() {
+        let prev = ii.saturating_sub(1);
+        let next = (ii + 1).min(positions.len() - 1);
+        let dt = positions[next].time - positions[prev].time;
+        let mut ret = [0.; 3];
+        if dt > 0. {
+            for axis in 0..3 {
+                ret[axis] = (values[next][axis] - values[prev][axis]) / dt;
+            }
+        }
+        out(ii, ret);
+    }
+}
+
+/// Get the signed shortest angle to turn from `from` to `to`, in degrees
+fn angle_delta(from: f64, to: f64) -> f64 {
+    let delta = (to - from).rem_euclid(360.);
+    if delta > 180. { delta - 360. } else { delta }
+}
+
+/// Find the jumps and falls in a segment starting at sample `base`
+fn find_drops(positions: &[Position], samples: &[Sample], base: usize,
+              events: &mut Vec<Event>) {
+    let vz = |ii: usize| samples[ii].velocity[2];
+    let event = |kind, start: usize, end: usize, magnitude| Event {
+        kind,
+        start: base + start,
+        end: base + end,
+        duration: positions[end].time - positions[start].time,
+        magnitude,
+    };
+
+    let mut ii = 0;
+    while ii < positions.len() {
+        if vz(ii) > JUMP_RISE_SPEED {
+            // Rising, find the peak and where the drop after it ends
+            let start = ii;
+            while ii + 1 < positions.len() && vz(ii + 1) > 0. {
+                ii += 1;
+            }
+            while ii + 1 < positions.len() && vz(ii + 1) < 0. {
+                ii += 1;
+            }
+
+            // Differences smear the top out, so go by the highest sample
+            let peak = (start..=ii).fold(start, |acc, x| {
+                if positions[x].z > positions[acc].z { x } else { acc }
+            });
+
+            let rise = positions[peak].z - positions[start].z;
+            let drop = positions[peak].z - positions[ii].z;
+            let duration = positions[ii].time - positions[start].time;
+            if duration <= JUMP_MAX_DURATION && rise <= JUMP_MAX_HEIGHT &&
+                    drop < FALL_MIN_HEIGHT {
+                events.push(event(EventKind::Jump, start, ii, rise));
+            } else if drop >= FALL_MIN_HEIGHT {
+                events.push(event(EventKind::Fall, peak, ii, drop));
+            }
+        } else if vz(ii) < FALL_SPEED {
+            let start = ii.saturating_sub(1);
+            while ii + 1 < positions.len() && vz(ii + 1) < 0. {
+                ii += 1;
+            }
+
+            let drop = positions[start].z - positions[ii].z;
+            if drop >= FALL_MIN_HEIGHT {
+                events.push(event(EventKind::Fall, start, ii, drop));
+            }
+        }
+        ii += 1;
+    }
+}
+
+/// Find the rides in a segment starting at sample `base`
+fn find_rides(positions: &[Position], samples: &[Sample], base: usize,
+              events: &mut Vec<Event>) {
+    let mut ii = 0;
+    while ii < positions.len() {
+        if samples[ii].speed <= RIDE_SPEED {
+            ii += 1;
+            continue;
+        }
+
+        let start = ii;
+        while ii + 1 < positions.len() && samples[ii + 1].speed > RIDE_SPEED {
+            ii += 1;
+        }
+
+        let duration = positions[ii].time - positions[start].time;
+        if duration >= RIDE_MIN_DURATION {
+            let distance: f64 = positions[start..=ii].windows(2)
+                .map(|x| ((x[1].x - x[0].x).powi(2) +
+                          (x[1].y - x[0].y).powi(2)).sqrt())
+                .sum();
+            events.push(Event {
+                kind: EventKind::Ride,
+                start: base + start,
+                end: base + ii,
+                duration,
+                magnitude: distance / duration,
+            });
+        }
+        ii += 1;
+    }
+}
+
+impl Positions {
+    /// Analyze the motion in the recording, splitting it into segments with
+    /// `options` first
+    pub fn analyze(&self, options: &SegmentOptions) -> Analysis {
+        let mut samples = vec![Sample::default(); self.positions.len()];
+        let mut events  = Vec::new();
+        let mut summary = Summary {
+            samples: self.positions.len(),
+            ..Summary::default()
+        };
+
+        for segment in self.segments(options) {
+            let base = segment.start;
+            let positions = segment.positions;
+            let out = &mut samples[base..base + positions.len()];
+
+            // Velocity, then acceleration from it
+            let coords: Vec<[f64; 3]> = positions.iter()
+                .map(|x| [x.x, x.y, x.z]).collect();
+            derivative(positions, &coords, |ii, vel| out[ii].velocity = vel);
+            let velocities: Vec<[f64; 3]> = out.iter()
+                .map(|x| x.velocity).collect();
+            derivative(positions, &velocities,
+                       |ii, acc| out[ii].acceleration = acc);
+
+            for sample in out.iter_mut() {
+                let [vx, vy, _] = sample.velocity;
+                sample.speed = (vx * vx + vy * vy).sqrt();
+                if sample.speed > MIN_HEADING_SPEED {
+                    sample.heading =
+                        Some(vy.atan2(vx).to_degrees().rem_euclid(360.));
+                }
+            }
+
+            // Turn rates between samples which both have a heading
+            for ii in 1..positions.len() {
+                let dt = positions[ii].time - positions[ii - 1].time;
+                if let (Some(a), Some(b), true) =
+                        (out[ii - 1].heading, out[ii].heading, dt > 0.) {
+                    out[ii].heading_change = angle_delta(a, b) / dt;
+                }
+            }
+
+            find_drops(positions, out, base, &mut events);
+            find_rides(positions, out, base, &mut events);
+
+            summary.segments += 1;
+            summary.duration += segment.duration;
+            summary.distance += segment.length;
+            for pair in positions.windows(2) {
+                let dz = pair[1].z - pair[0].z;
+                if dz > 0. {
+                    summary.climb += dz;
+                } else {
+                    summary.descent -= dz;
+                }
+            }
+        }
+
+        events.sort_by_key(|x| x.start);
+        summary.max_speed = samples.iter().map(|x| x.speed)
+            .fold(0., f64::max);
+        if summary.duration > 0. {
+            summary.average_speed = summary.distance / summary.duration;
+        }
+        for event in &events {
+            match event.kind {
+                EventKind::Jump => summary.jumps += 1,
+                EventKind::Fall => summary.falls += 1,
+                EventKind::Ride => {
+                    summary.rides += 1;
+                    summary.ride_time += event.duration;
+                }
+            }
+        }
+
+        Analysis { samples, events, summary }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a recording from `(time, x, y, z)` samples on map 0
+    fn timed_recording(samples: &[(f64, f64, f64, f64)]) -> Positions {
+        Positions { positions: samples.iter().map(|&(time, x, y, z)| {
+            Position { time, x, y, z, map_id: 0, angle: 0. }
+        }).collect() }
+    }
+
+    #[test]
+    fn velocity_and_heading() {
+        // Run along x then turn towards y
+        let positions = timed_recording(&[
+            (0., 0., 0., 0.),
+            (1., 7., 0., 0.),
+            (2., 14., 0., 0.),
+            (3., 14., 7., 0.),
+        ]);
+        let analysis = positions.analyze(&SegmentOptions::default());
+        let samples = &analysis.samples;
+
+        assert_eq!(samples[1].velocity, [7., 0., 0.]);
+        assert_eq!(samples[1].speed, 7.);
+        assert_eq!(samples[1].heading, Some(0.));
+        assert_eq!(samples[3].heading, Some(90.));
+        assert_eq!(samples[2].heading, Some(45.));
+        assert_eq!(samples[2].heading_change, 45.);
+        assert_eq!(samples[2].acceleration, [-3.5, 3.5, 0.]);
+        assert!(analysis.events.is_empty());
+
+        let summary = analysis.summary;
+        assert_eq!((summary.samples, summary.segments), (4, 1));
+        assert_eq!((summary.duration, summary.distance), (3., 21.));
+        assert_eq!(summary.average_speed, 7.);
+    }
+
+    #[test]
+    fn jumps_falls_and_rides_are_detected() {
+        let mut samples = Vec::new();
+
+        // Run, jump, run
+        let mut time = 0.;
+        for &z in &[0., 0., 0., 0.8, 1.4, 1.6, 1.2, 0.5, 0., 0., 0.] {
+            samples.push((time, time * 7., 0., z));
+            time += 0.1;
+        }
+
+        // Run off a cliff
+        for ii in 0..10 {
+            let z = -(ii as f64 * 0.1).powi(2) * 10.;
+            samples.push((time, time * 7., 0., z));
+            time += 0.1;
+        }
+        let ground = samples.last().unwrap().3;
+        samples.push((time, time * 7., 0., ground));
+        time += 0.1;
+
+        // Mount up and ride for 5 seconds
+        let start = samples.last().unwrap().1;
+        for ii in 1..=25 {
+            samples.push((time, start + ii as f64 * 0.2 * 14., 0., ground));
+            time += 0.2;
+        }
+
+        let analysis = timed_recording(&samples)
+            .analyze(&SegmentOptions::default());
+        let kinds: Vec<EventKind> = analysis.events.iter()
+            .map(|x| x.kind).collect();
+        assert_eq!(kinds, vec![EventKind::Jump, EventKind::Fall,
+                               EventKind::Ride]);
+
+        let jump = analysis.events[0];
+        assert!((jump.magnitude - 1.6).abs() < 1e-9);
+        let fall = analysis.events[1];
+        assert!(fall.magnitude > 7.);
+        let ride = analysis.events[2];
+        assert!((ride.magnitude - 14.).abs() < 0.5, "{:?}", ride);
+        assert!(ride.duration >= 4.);
+
+        let summary = analysis.summary;
+        assert_eq!((summary.jumps, summary.falls, summary.rides), (1, 1, 1));
+        assert!(summary.max_speed > 13.);
+    }
+}
@@ -1,58 +1,120 @@
-use std::io;
+//! Parsing of player positions logged by the position logging addon, and
+//! conversion between it and other position log formats
+
+#![allow(clippy::write_with_newline)]
+
 use std::path::Path;
 
-#[derive(Debug, Clone, Copy)]
+mod error;
+mod fields;
+mod lua;
+mod lua_table;
+mod csv;
+mod jsonl;
+mod format;
+mod segment;
+mod interpolate;
+mod kinematics;
+mod target;
+
+pub use error::{Strictness, ParseError, ParseErrorKind, Error};
+pub use format::{Format, FORMATS};
+pub use segment::{Segment, SegmentOptions, Split};
+pub use interpolate::Interpolation;
+pub use kinematics::{Sample, EventKind, Event, Summary, Analysis};
+pub use target::{Target, Approach, Platform};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Position {
     pub time:   f64,
     pub x:      f64,
     pub y:      f64,
+    pub z:      f64,
     pub map_id: u32,
     pub angle:  f64,
 }
 
+/// A break in the recording, where two consecutive samples are further apart
+/// in time than expected, eg. from a loading screen or the game being closed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gap {
+    /// Index of the last sample before the gap
+    pub start: usize,
+
+    /// Index of the first sample after the gap
+    pub end: usize,
+
+    /// Time between the two samples in seconds
+    pub duration: f64,
+
+    /// Distance between the two samples
+    pub distance: f64,
+}
+
+/// Default time between samples in seconds above which there is a gap
+pub const DEFAULT_GAP_THRESHOLD: f64 = 5.;
+
 #[derive(Debug, Clone)]
 pub struct Positions {
     pub positions: Vec<Position>,
 }
 
 impl Positions {
-    pub fn from_lua<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    /// Parse the positions in the addon's LUA saved variable data `data`,
+    /// see `parse`
+    pub fn parse_lua(data: &str, strictness: Strictness)
+            -> Result<(Self, Vec<ParseError>), ParseError> {
+        Self::parse(data, Format::Addon, strictness)
+    }
+
+    /// Find the gaps in the recording, where consecutive samples are more
+    /// than `threshold` seconds apart
+    pub fn gaps(&self, threshold: f64) -> Vec<Gap> {
+        self.positions.windows(2).enumerate()
+            .filter(|(_, poss)| poss[1].time - poss[0].time > threshold)
+            .map(|(ii, poss)| Gap {
+                start:    ii,
+                end:      ii + 1,
+                duration: poss[1].time - poss[0].time,
+                distance: ((poss[1].x - poss[0].x).powi(2) +
+                           (poss[1].y - poss[0].y).powi(2) +
+                           (poss[1].z - poss[0].z).powi(2)).sqrt(),
+            })
+            .collect()
+    }
+
+    /// Load the positions from the addon's LUA saved variable file at
+    /// `path`, see `parse`
+    pub fn from_lua<P: AsRef<Path>>(path: P, strictness: Strictness)
+            -> Result<(Self, Vec<ParseError>), Error> {
         // Read the LUA saved variable data
         let data = std::fs::read_to_string(path)?;
-
-        // Create the empty position vector
-        let mut positions = Vec::new();
-
-        let mut last_time = 0.0;
-        for data in data.split("LOCATION237945_").skip(1) {
-            let mut data = data.splitn(2, "\",").next().unwrap().split("_");
-            let x:      f64 = data.next().unwrap().parse().unwrap();
-            let y:      f64 = data.next().unwrap().parse().unwrap();
-            let _z:     f64 = data.next().unwrap().parse().unwrap();
-            let map_id: u32 = data.next().unwrap().parse().unwrap();
-            let angle:  f64 = data.next().unwrap().parse().unwrap();
-            let time:   f64 = data.next().unwrap().parse().unwrap();
-            assert!(data.next().is_none());
-
-            if last_time == 0. {
-                last_time = time;
-            }
-
-            if (time - last_time) > 5. {
-                print!("Updated {}\n", time);
-                print!("{} {} {}\n", x, y, angle);
-            }
-
-            last_time = time;
-
-            positions.push(Position {
-                time, x, y, map_id, angle
-            });
-        }
-
-        Ok(Positions {
-            positions
-        })
+        Ok(Self::parse_lua(&data, strictness)?)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps_are_found() {
+        let data = "{
+            \"LOCATION237945_0_0_0_0_0_100\",
+            \"LOCATION237945_1_0_0_0_0_101\",
+            \"LOCATION237945_4_4_0_0_0_110\",
+            \"LOCATION237945_4_4_0_0_0_112\",
+            \"LOCATION237945_4_4_2_0_0_114.5\",
+        }";
+        let (positions, _) =
+            Positions::parse_lua(data, Strictness::Strict).unwrap();
+
+        assert_eq!(positions.gaps(DEFAULT_GAP_THRESHOLD), vec![Gap {
+            start: 1, end: 2, duration: 9., distance: 5.,
+        }]);
+        assert_eq!(positions.gaps(2.), vec![
+            Gap { start: 1, end: 2, duration: 9.,  distance: 5. },
+            Gap { start: 3, end: 4, duration: 2.5, distance: 2. },
+        ]);
+    }
+}
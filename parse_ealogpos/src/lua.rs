@@ -0,0 +1,213 @@
+//! Parser for the LUA saved variables written by the position logging addon
+//!
+//! The addon saves each position as a string in a LUA table, eg.
+//!
+//! ```text
+//! EALogPos = {
+//!     "LOCATION237945_-8913.2_554.6_93.7_0_178.5_81234.125",
+//! }
+//! ```
+//!
+//! The fields after the `LOCATION237945_` magic are separated by `_` and are
+//! the x, y and z coordinates, the map id, the facing angle in degrees and
+//! the time in seconds. Everything outside of the strings is ignored.
+
+use std::io::{self, Write};
+
+use crate::Position;
+use crate::error::{Strictness, ParseError, ParseErrorKind, Collector};
+
+/// Magic which starts every position record
+pub(crate) const MAGIC: &str = "LOCATION237945_";
+
+/// Name of the variable positions are written to
+const VARIABLE: &str = "EALogPos";
+
+/// Names of the fields of a record, in order
+const FIELDS: [&str; 6] = ["x", "y", "z", "map_id", "angle", "time"];
+
+/// Parse a single record, `record` is the text between the magic and the
+/// closing quote and starts at byte `offset` of the data
+fn parse_record(record: &str, offset: usize)
+        -> Result<Position, (usize, ParseErrorKind)> {
+    let mut values = [0f64; 6];
+    let mut field_start = offset;
+    let mut fields = record.split('_');
+    for (ii, &name) in FIELDS.iter().enumerate() {
+        let field = fields.next().ok_or(
+            (offset + record.len(), ParseErrorKind::MissingField(name)))?;
+
+        // The map id is an integer, everything else is a float
+        values[ii] = if name == "map_id" {
+            field.parse::<u32>().map(|x| x as f64).ok()
+        } else {
+            field.parse().ok()
+        }.ok_or_else(|| (field_start, ParseErrorKind::InvalidField {
+            field: name,
+            value: field.into(),
+        }))?;
+        if !values[ii].is_finite() {
+            return Err((field_start, ParseErrorKind::NonFinite(name)));
+        }
+
+        field_start += field.len() + 1;
+    }
+
+    if field_start <= offset + record.len() {
+        return Err((field_start - 1, ParseErrorKind::TrailingData(
+            record[field_start - 1 - offset..].into())));
+    }
+
+    let [x, y, z, map_id, angle, time] = values;
+    Ok(Position { time, x, y, z, map_id: map_id as u32, angle })
+}
+
+/// Parse all position records in `data`. Bad records are handled according
+/// to `strictness`, the errors for the ones which were skipped are returned
+/// along with the positions if it is `Strictness::Collect`.
+pub fn parse(data: &str, strictness: Strictness)
+        -> Result<(Vec<Position>, Vec<ParseError>), ParseError> {
+    let mut positions = Vec::new();
+    let mut errors    = Collector::new(data, strictness);
+
+    let mut cursor = 0;
+    while let Some(start) = data[cursor..].find(MAGIC) {
+        let start = cursor + start + MAGIC.len();
+
+        // The record ends at the closing quote, which must be on the same
+        // line
+        let rest = &data[start..];
+        let result = match rest.find(['"', '\n']) {
+            Some(end) if rest.as_bytes()[end] == b'"' => {
+                cursor = start + end + 1;
+                parse_record(&rest[..end], start)
+            }
+            end => {
+                cursor = start + end.unwrap_or(rest.len());
+                Err((start, ParseErrorKind::Unterminated))
+            }
+        };
+
+        match result {
+            Ok(position) => positions.push(position),
+            Err((offset, kind)) => errors.bad_record(offset, kind)?,
+        }
+    }
+
+    Ok((positions, errors.errors))
+}
+
+/// Write `positions` in the addon's format
+pub fn write<W: Write>(mut out: W, positions: &[Position]) -> io::Result<()> {
+    write!(out, "{} = {{\n", VARIABLE)?;
+    for pos in positions {
+        write!(out, "\t\"{}{}_{}_{}_{}_{}_{}\",\n", MAGIC,
+               pos.x, pos.y, pos.z, pos.map_id, pos.angle, pos.time)?;
+    }
+    write!(out, "}}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A saved variables file with two good records
+    const GOOD: &str = "\
+EALogPos = {
+\t\"LOCATION237945_-8913.25_554.5_93.75_0_3.25_81234.5\",
+\t\"LOCATION237945_1.5_-2.5_3_1_0_81235\",
+}
+";
+
+    /// A saved variables file with a good record between two bad ones
+    const BAD: &str = "\
+EALogPos = {
+\t\"LOCATION237945_1_2_3_0_0.5\",
+\t\"LOCATION237945_1_2_3_0_0.5_100\",
+\t\"LOCATION237945_1_2_x_0_0.5_100\",
+}
+";
+
+    #[test]
+    fn good_records_parse() {
+        let (positions, errors) = parse(GOOD, Strictness::Strict).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(positions.len(), 2);
+
+        let pos = positions[0];
+        assert_eq!((pos.x, pos.y, pos.z, pos.map_id, pos.angle, pos.time),
+                   (-8913.25, 554.5, 93.75, 0, 3.25, 81234.5));
+        let pos = positions[1];
+        assert_eq!((pos.x, pos.y, pos.z, pos.map_id, pos.angle, pos.time),
+                   (1.5, -2.5, 3., 1, 0., 81235.));
+    }
+
+    #[test]
+    fn strict_fails_on_first_bad_record() {
+        let err = parse(BAD, Strictness::Strict).unwrap_err();
+        assert_eq!(err, ParseError {
+            offset: BAD.find("_0.5\"").unwrap() + 4,
+            line:   2,
+            kind:   ParseErrorKind::MissingField("time"),
+        });
+    }
+
+    #[test]
+    fn collect_returns_bad_records() {
+        let (positions, errors) = parse(BAD, Strictness::Collect).unwrap();
+        assert_eq!(positions.len(), 1);
+        assert_eq!(positions[0].time, 100.);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[1], ParseError {
+            offset: BAD.find("x_0").unwrap(),
+            line:   4,
+            kind:   ParseErrorKind::InvalidField {
+                field: "z",
+                value: "x".into(),
+            },
+        });
+    }
+
+    #[test]
+    fn skip_ignores_bad_records() {
+        let (positions, errors) = parse(BAD, Strictness::Skip).unwrap();
+        assert_eq!(positions.len(), 1);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn trailing_and_unterminated_records_are_errors() {
+        let data = "{\"LOCATION237945_1_2_3_0_0_5_6\",\n\
+                    \"LOCATION237945_1_2_3_0_0_5\n}\n\
+                    \"LOCATION237945_1_2_3_0_0_5";
+        let (positions, errors) = parse(data, Strictness::Collect).unwrap();
+        assert!(positions.is_empty());
+        assert_eq!(errors.iter().map(|x| (x.line, x.kind.clone()))
+                   .collect::<Vec<_>>(), vec![
+            (1, ParseErrorKind::TrailingData("_6".into())),
+            (2, ParseErrorKind::Unterminated),
+            (4, ParseErrorKind::Unterminated),
+        ]);
+    }
+
+    #[test]
+    fn non_finite_fields_are_errors() {
+        let data = "\"LOCATION237945_1_inf_3_0_0_5\"\n\
+                    \"LOCATION237945_1_2_3_0_NaN_5\"";
+        let (positions, errors) = parse(data, Strictness::Collect).unwrap();
+        assert!(positions.is_empty());
+        assert_eq!(errors.iter().map(|x| x.kind.clone())
+                   .collect::<Vec<_>>(), vec![
+            ParseErrorKind::NonFinite("y"),
+            ParseErrorKind::NonFinite("angle"),
+        ]);
+    }
+
+    #[test]
+    fn text_outside_records_is_ignored() {
+        let data = "-- comment\nEALogPos = {}\nOther = \"LOCATION\"\n";
+        let (positions, errors) = parse(data, Strictness::Strict).unwrap();
+        assert!(positions.is_empty());
+        assert!(errors.is_empty());
+    }
+}
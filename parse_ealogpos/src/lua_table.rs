@@ -0,0 +1,543 @@
+//! Position logs stored as native LUA tables in a SavedVariables file
+//!
+//! Any table with `time`, `x`, `y` and `map` (or `map_id`) keys is a
+//! position, optionally with `z` and `angle`. Tables of 5 or 6 numbers are
+//! positions too, in the order `time, x, y, z, map, angle` (`angle` being
+//! optional). Positions can be nested anywhere in the saved variables, eg.
+//!
+//! ```text
+//! PositionLog = {
+//!     ["samples"] = {
+//!         { time = 81234.5, x = -8913.2, y = 554.6, z = 93.7, map = 0 },
+//!         { 81235.5, -8914.2, 554.6, 93.7, 0, 178.5 }, -- [2]
+//!     },
+//! }
+//! ```
+
+use std::io::{self, Write};
+
+use crate::{Position, fields};
+use crate::error::{Strictness, ParseError, ParseErrorKind, Collector};
+use crate::error::MAX_DEPTH;
+
+/// Name of the variable positions are written to
+const VARIABLE: &str = "PositionLog";
+
+/// A parsed LUA value
+#[derive(Debug, Clone, PartialEq)]
+enum Lua {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Table(Table),
+}
+
+/// A parsed LUA table
+#[derive(Debug, Clone, PartialEq)]
+struct Table {
+    /// Byte offset of the opening brace
+    offset: usize,
+
+    /// Values without keys, in order
+    array: Vec<Lua>,
+
+    /// Values with explicit keys, in order
+    hash: Vec<(Lua, Lua)>,
+}
+
+/// A recursive descent parser for the subset of LUA used by SavedVariables
+/// files, which is assignments of constant values to global variables
+struct Parser<'a> {
+    /// The data being parsed
+    data: &'a str,
+
+    /// Current byte offset into `data`
+    pos: usize,
+
+    /// Number of tables the parser is inside of
+    depth: usize,
+}
+
+/// A syntax error, the byte offset and what was expected there
+type SyntaxError = (usize, String);
+
+impl<'a> Parser<'a> {
+    /// Get the data from the current position on
+    fn rest(&self) -> &'a str {
+        &self.data[self.pos..]
+    }
+
+    /// Get the level of a long bracket (`[[`, `[=[`, ...) at the current
+    /// position, if there is one
+    fn long_bracket(&self) -> Option<usize> {
+        let rest = self.rest().strip_prefix('[')?;
+        let level = rest.len() - rest.trim_start_matches('=').len();
+        if rest[level..].starts_with('[') {
+            Some(level)
+        } else {
+            None
+        }
+    }
+
+    /// Parse a long bracket string, returning its contents
+    fn long_string(&mut self, level: usize) -> Result<String, SyntaxError> {
+        let close = format!("]{}]", "=".repeat(level));
+        let start = self.pos + level + 2;
+        let len = self.data[start..].find(&close)
+            .ok_or((self.pos, format!("closing `{}`", close)))?;
+        self.pos = start + len + close.len();
+
+        // A newline right after the opening bracket is skipped
+        let contents = &self.data[start..start + len];
+        Ok(contents.strip_prefix('\n').unwrap_or(contents).into())
+    }
+
+    /// Skip whitespace and comments
+    fn skip(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+
+            if !trimmed.starts_with("--") {
+                return Ok(());
+            }
+            self.pos += 2;
+            match self.long_bracket() {
+                Some(level) => {
+                    self.long_string(level)?;
+                }
+                None => {
+                    self.pos += self.rest().find('\n')
+                        .unwrap_or(self.rest().len());
+                }
+            }
+        }
+    }
+
+    /// Consume `token` if it's next, after any whitespace and comments
+    fn eat(&mut self, token: &str) -> Result<bool, SyntaxError> {
+        self.skip()?;
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Consume `token`, which must be next
+    fn expect(&mut self, token: &str) -> Result<(), SyntaxError> {
+        if self.eat(token)? {
+            Ok(())
+        } else {
+            Err((self.pos, format!("`{}`", token)))
+        }
+    }
+
+    /// Parse a name, if there is one next
+    fn name(&mut self) -> Result<Option<&'a str>, SyntaxError> {
+        self.skip()?;
+        let rest = self.rest();
+        let len = rest.find(|x: char| !x.is_ascii_alphanumeric() && x != '_')
+            .unwrap_or(rest.len());
+        if len == 0 || rest.as_bytes()[0].is_ascii_digit() {
+            return Ok(None);
+        }
+
+        let start = self.pos;
+        self.pos += len;
+        Ok(Some(&self.data[start..self.pos]))
+    }
+
+    /// Parse a quoted string, the opening quote is next
+    fn quoted_string(&mut self) -> Result<String, SyntaxError> {
+        let start = self.pos;
+        let quote = self.rest().chars().next().unwrap();
+        self.pos += 1;
+
+        let mut ret = String::new();
+        let mut chars = self.rest().char_indices();
+        loop {
+            let (idx, chr) = chars.next()
+                .filter(|x| x.1 != '\n')
+                .ok_or((start, format!("closing `{}`", quote)))?;
+            match chr {
+                _ if chr == quote => {
+                    self.pos += idx + 1;
+                    return Ok(ret);
+                }
+                '\\' => {
+                    let (_, escape) = chars.next()
+                        .ok_or((start, format!("closing `{}`", quote)))?;
+                    ret.push(match escape {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        'a' => '\x07',
+                        'b' => '\x08',
+                        'f' => '\x0c',
+                        'v' => '\x0b',
+                        '0'..='9' => {
+                            // Up to 3 decimal digits of a byte value
+                            let mut val = escape.to_digit(10).unwrap();
+                            for _ in 0..2 {
+                                let next = chars.clone().next()
+                                    .and_then(|x| x.1.to_digit(10));
+                                match next {
+                                    Some(digit) => {
+                                        val = val * 10 + digit;
+                                        chars.next();
+                                    }
+                                    None => break,
+                                }
+                            }
+                            char::from_u32(val).unwrap_or('\u{fffd}')
+                        }
+                        other => other,
+                    });
+                }
+                _ => ret.push(chr),
+            }
+        }
+    }
+
+    /// Parse a number, including a leading minus sign
+    fn number(&mut self) -> Result<f64, SyntaxError> {
+        let start = self.pos;
+        let negative = self.rest().starts_with('-');
+        if negative {
+            self.pos += 1;
+            self.skip()?;
+        }
+
+        // Numbers run until something which can't be in one, signs can
+        // only follow the exponent of decimal numbers
+        let rest = self.rest();
+        let bytes = rest.as_bytes();
+        let hex = rest.starts_with("0x") || rest.starts_with("0X");
+        let mut len = 0;
+        while let Some(&byte) = bytes.get(len) {
+            let exponent_sign = (byte == b'+' || byte == b'-') && !hex &&
+                len > 0 && bytes[len - 1].eq_ignore_ascii_case(&b'e');
+            if !byte.is_ascii_alphanumeric() && byte != b'.' &&
+                    !exponent_sign {
+                break;
+            }
+            len += 1;
+        }
+
+        let token = &rest[..len];
+        let value = if let Some(hex) = token.strip_prefix("0x")
+                .or_else(|| token.strip_prefix("0X")) {
+            u64::from_str_radix(hex, 16).ok().map(|x| x as f64)
+        } else {
+            token.parse::<f64>().ok()
+                .filter(|_| !token.starts_with(|x: char| x.is_alphabetic()))
+        }.ok_or((start, "a value".to_string()))?;
+
+        self.pos += len;
+        Ok(if negative { -value } else { value })
+    }
+
+    /// Parse a table, the opening brace is next
+    fn table(&mut self) -> Result<Table, SyntaxError> {
+        let mut table = Table {
+            offset: self.pos,
+            array:  Vec::new(),
+            hash:   Vec::new(),
+        };
+        self.pos += 1;
+
+        loop {
+            if self.eat("}")? {
+                return Ok(table);
+            }
+
+            self.skip()?;
+            if self.long_bracket().is_none() && self.eat("[")? {
+                // `[key] = value`
+                let key = self.value()?;
+                self.expect("]")?;
+                self.expect("=")?;
+                table.hash.push((key, self.value()?));
+            } else {
+                // `name = value`, or a positional value
+                let start = self.pos;
+                let name = self.name()?.map(String::from);
+                match name {
+                    Some(name) if self.eat("=")? => {
+                        table.hash.push((Lua::String(name), self.value()?));
+                    }
+                    _ => {
+                        self.pos = start;
+                        table.array.push(self.value()?);
+                    }
+                }
+            }
+
+            if !self.eat(",")? && !self.eat(";")? {
+                self.expect("}")?;
+                return Ok(table);
+            }
+        }
+    }
+
+    /// Parse any value
+    fn value(&mut self) -> Result<Lua, SyntaxError> {
+        self.skip()?;
+        if let Some(level) = self.long_bracket() {
+            return Ok(Lua::String(self.long_string(level)?));
+        }
+
+        let start = self.pos;
+        match self.rest().chars().next() {
+            Some('{') if self.depth == MAX_DEPTH => {
+                Err((start, format!("at most {} nested tables", MAX_DEPTH)))
+            }
+            Some('{') => {
+                self.depth += 1;
+                let table = self.table()?;
+                self.depth -= 1;
+                Ok(Lua::Table(table))
+            }
+            Some('"') | Some('\'') => Ok(Lua::String(self.quoted_string()?)),
+            Some(chr) if chr == '-' || chr == '.' || chr.is_ascii_digit() => {
+                Ok(Lua::Number(self.number()?))
+            }
+            _ => match self.name()? {
+                Some("nil")   => Ok(Lua::Nil),
+                Some("true")  => Ok(Lua::Bool(true)),
+                Some("false") => Ok(Lua::Bool(false)),
+                _ => Err((start, "a value".into())),
+            },
+        }
+    }
+
+    /// Parse the whole file, a list of `name = value` assignments
+    fn file(&mut self) -> Result<Vec<(String, Lua)>, SyntaxError> {
+        let mut ret = Vec::new();
+        loop {
+            self.skip()?;
+            if self.pos == self.data.len() {
+                return Ok(ret);
+            }
+
+            let pos = self.pos;
+            let mut name = self.name()?.ok_or((pos, "a name".into()))?;
+            if name == "local" {
+                let pos = self.pos;
+                name = self.name()?.ok_or((pos, "a name".into()))?;
+            }
+            let name = name.to_string();
+            self.expect("=")?;
+            ret.push((name, self.value()?));
+            self.eat(";")?;
+        }
+    }
+}
+
+/// Get the text of a LUA value, for error messages
+fn describe(value: &Lua) -> String {
+    match value {
+        Lua::Nil        => "nil".into(),
+        Lua::Bool(val)  => val.to_string(),
+        Lua::Number(n)  => n.to_string(),
+        Lua::String(s)  => s.clone(),
+        Lua::Table(_)   => "a table".into(),
+    }
+}
+
+/// Get a field value from a LUA value
+fn field(value: &Lua) -> Option<fields::Value> {
+    match value {
+        Lua::Nil => None,
+        Lua::Number(val) => Some(Ok(*val)),
+        other => Some(Err(describe(other))),
+    }
+}
+
+/// Find the positions in `table` and the tables in it, in order
+fn find_positions(table: &Table, positions: &mut Vec<Position>,
+                  errors: &mut Collector) -> Result<(), ParseError> {
+    let get = |name: &str| {
+        table.hash.iter().find(|x| x.0 == Lua::String(name.into()))
+            .and_then(|x| field(&x.1))
+    };
+
+    // Tables with all the required position keys are positions, anything
+    // else with some of them, like a frame anchor, is just searched
+    let has = |name: &str| get(name).is_some();
+    if has("time") && has("x") && has("y") && (has("map") || has("map_id")) {
+        match fields::position(get) {
+            Ok(position) => positions.push(position),
+            Err(kind) => errors.bad_record(table.offset, kind)?,
+        }
+        return Ok(());
+    }
+
+    // So are lists of 5 or 6 numbers
+    let numbers = table.array.iter().map(|x| match x {
+        Lua::Number(val) => Some(*val),
+        _ => None,
+    }).collect::<Option<Vec<f64>>>();
+    if let Some(numbers) = numbers {
+        if table.hash.is_empty() && (numbers.len() == 5 || numbers.len() == 6) {
+            let result = fields::position(|name| {
+                let idx = fields::NAMES.iter().position(|&x| x == name)?;
+                numbers.get(idx).map(|&x| Ok(x))
+            });
+            match result {
+                Ok(position) => positions.push(position),
+                Err(kind) => errors.bad_record(table.offset, kind)?,
+            }
+            return Ok(());
+        }
+    }
+
+    for value in table.array.iter().chain(table.hash.iter().map(|x| &x.1)) {
+        if let Lua::Table(table) = value {
+            find_positions(table, positions, errors)?;
+        }
+    }
+    Ok(())
+}
+
+/// Parse all positions in the LUA `data`. Bad records are handled according
+/// to `strictness`, the errors for the ones which were skipped are returned
+/// along with the positions if it is `Strictness::Collect`. Syntax errors are
+/// always returned as an error.
+pub fn parse(data: &str, strictness: Strictness)
+        -> Result<(Vec<Position>, Vec<ParseError>), ParseError> {
+    let mut positions = Vec::new();
+    let mut errors    = Collector::new(data, strictness);
+
+    let mut parser = Parser { data, pos: 0, depth: 0 };
+    let variables = parser.file().map_err(|(offset, expected)| {
+        errors.error(offset, ParseErrorKind::Syntax(expected))
+    })?;
+
+    for (_, value) in &variables {
+        if let Lua::Table(table) = value {
+            find_positions(table, &mut positions, &mut errors)?;
+        }
+    }
+
+    Ok((positions, errors.errors))
+}
+
+/// Write `positions` as a LUA SavedVariables file
+pub fn write<W: Write>(mut out: W, positions: &[Position]) -> io::Result<()> {
+    write!(out, "{} = {{\n", VARIABLE)?;
+    for pos in positions {
+        write!(out, "\t{{ time = {}, x = {}, y = {}, z = {}, map = {}, \
+                     angle = {} }},\n",
+               pos.time, pos.x, pos.y, pos.z, pos.map_id, pos.angle)?;
+    }
+    write!(out, "}}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A SavedVariables file with positions in a few different forms
+    const SAVED: &str = "\
+-- Saved by some addon
+--[==[ a long
+comment ]==]
+Settings = { enabled = true, [\"name\"] = 'a\\'b', [[long]], nothing = nil }
+local Version = 0x10;
+PositionLog = {
+\t[\"samples\"] = {
+\t\t{ time = 100, x = -1.5e2, y = 2, z = - 3, map = 1 },
+\t\t{ 101, 1, 2, 3, 1, 0.5 }, -- [2]
+\t\t{ time = 102, x = \"oops\", y = 2, map = 1 },
+\t\t{ 1, 2, 3 },
+\t},
+\tmore = { { [\"time\"] = 103; [\"x\"] = 1; [\"y\"] = 2; [\"map_id\"] = 0 } },
+}
+";
+
+    #[test]
+    fn tables_parse() {
+        let (positions, errors) = parse(SAVED, Strictness::Collect).unwrap();
+        assert_eq!(positions.iter()
+                   .map(|x| (x.time, x.x, x.y, x.z, x.map_id, x.angle))
+                   .collect::<Vec<_>>(), vec![
+            (100., -150., 2., -3., 1, 0.),
+            (101., 1., 2., 3., 1, 0.5),
+            (103., 1., 2., 0., 0, 0.),
+        ]);
+
+        assert_eq!(errors, vec![ParseError {
+            offset: SAVED.find("{ time = 102").unwrap(),
+            line:   10,
+            kind:   ParseErrorKind::InvalidField {
+                field: "x",
+                value: "oops".into(),
+            },
+        }]);
+    }
+
+    #[test]
+    fn other_tables_with_position_keys_are_searched() {
+        let data = "\
+Settings = {
+    anchor = { x = 12, y = -40, point = \"CENTER\" },
+    log = { x = 1, { time = 100, x = 1, y = 2, map = 0 } },
+}
+";
+        let (positions, _) = parse(data, Strictness::Strict).unwrap();
+        assert_eq!(positions.iter().map(|x| (x.time, x.x, x.y))
+                   .collect::<Vec<_>>(), vec![(100., 1., 2.)]);
+    }
+
+    #[test]
+    fn syntax_errors_are_fatal() {
+        let data = "A = {\n  1, 2,\n  x = = 3,\n}\n";
+        for &strictness in &[Strictness::Strict, Strictness::Skip] {
+            let err = parse(data, strictness).unwrap_err();
+            assert_eq!(err, ParseError {
+                offset: data.find("= 3").unwrap(),
+                line:   3,
+                kind:   ParseErrorKind::Syntax("a value".into()),
+            });
+        }
+
+        let err = parse("A = \"abc\nB = 1", Strictness::Strict).unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::Syntax("closing `\"`".into()));
+    }
+
+    #[test]
+    fn deep_nesting_is_a_syntax_error() {
+        let data = format!("A = {}", "{".repeat(100000));
+        let err = parse(&data, Strictness::Skip).unwrap_err();
+        assert_eq!(err.offset, 4 + MAX_DEPTH);
+        assert_eq!(err.kind, ParseErrorKind::Syntax(
+            format!("at most {} nested tables", MAX_DEPTH)));
+    }
+
+    #[test]
+    fn non_finite_values_are_bad_records() {
+        let data = "A = { { time = 100, x = 1e999, y = 2, map = 0 } }\n";
+        let (positions, errors) = parse(data, Strictness::Collect).unwrap();
+        assert!(positions.is_empty());
+        assert_eq!(errors[0].kind, ParseErrorKind::NonFinite("x"));
+    }
+
+    #[test]
+    fn written_positions_parse() {
+        let positions = vec![Position {
+            time: 81234.5, x: -8913.25, y: 554.5, z: 93.75, map_id: 0,
+            angle: 3.25,
+        }];
+        let mut data = Vec::new();
+        write(&mut data, &positions).unwrap();
+
+        let data = String::from_utf8(data).unwrap();
+        let (parsed, _) = parse(&data, Strictness::Strict).unwrap();
+        assert_eq!(parsed, positions);
+    }
+}
@@ -0,0 +1,297 @@
+//! Command line tool for working with position logs
+
+#![allow(clippy::print_with_newline)]
+
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use parse_ealogpos::{Positions, Format, Strictness, Interpolation, FORMATS};
+use parse_ealogpos::{SegmentOptions, Analysis, EventKind};
+
+/// Usage text, `{}` is replaced with the program name
+const USAGE: &str = "\
+Usage: {} <command> [options]
+
+Commands:
+    convert [options] <input> <output>
+        Convert a position log to another format. The input format is
+        detected from the extension or contents, the output format from the
+        extension (`.lua` files are written as LUA tables, `.json` needs
+        --to jsonl).
+
+        --from <format>       Format of the input
+        --to <format>         Format of the output
+        --bad-records <mode>  `fail` on malformed records, `warn` and skip
+                              them (the default), or `skip` them silently
+        --resample <rate>     Resample to `rate` positions per second
+        --smooth              Interpolate with a Catmull-Rom spline rather
+                              than linearly when resampling
+
+    analyze [options] <input>...
+        Print a summary of the motion in each position log: distance, speeds,
+        climb, and the jumps, falls and mount or boat rides found in it.
+
+        --json                Print the summaries as a JSON array
+        --events              List each detected event as well
+        --gap <seconds>       Split recordings at gaps longer than this
+        --teleport <yards>    Split recordings at jumps further than this
+        --bad-records <mode>  As for `convert`
+
+    help
+        Print this message
+
+Formats: {formats}
+";
+
+/// Get the usage text for the program named `prog`
+fn usage(prog: &str) -> String {
+    let formats = FORMATS.iter().map(|x| x.name())
+        .collect::<Vec<_>>().join(", ");
+    USAGE.replacen("{}", prog, 1).replace("{formats}", &formats)
+}
+
+/// Parse a `--bad-records` mode
+fn parse_strictness(mode: &str) -> Result<Strictness, String> {
+    match mode {
+        "fail" => Ok(Strictness::Strict),
+        "warn" => Ok(Strictness::Collect),
+        "skip" => Ok(Strictness::Skip),
+        _ => Err(format!("Invalid --bad-records mode {:?}", mode)),
+    }
+}
+
+/// Load the positions in `path`, in `format` if given or the detected
+/// format otherwise, printing the bad records which were skipped
+fn load(path: &Path, format: Option<Format>, strictness: Strictness)
+        -> Result<Positions, Box<dyn Error>> {
+    let (positions, errors) = match format {
+        Some(format) => {
+            let data = std::fs::read_to_string(path)?;
+            Positions::parse(&data, format, strictness)?
+        }
+        None => Positions::load(path, strictness)?,
+    };
+
+    for err in &errors {
+        eprint!("Skipped bad record in {}: {}\n", path.display(), err);
+    }
+    Ok(positions)
+}
+
+/// The `convert` command
+fn convert<I: Iterator<Item = String>>(mut args: I)
+        -> Result<(), Box<dyn Error>> {
+    let mut from       = None;
+    let mut to         = None;
+    let mut strictness = Strictness::default();
+    let mut resample   = None;
+    let mut mode       = Interpolation::Linear;
+    let mut paths      = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next().ok_or_else(|| format!("{} requires a value", name))
+        };
+
+        match arg.as_str() {
+            "--from" => from = Some(value("--from")?.parse::<Format>()?),
+            "--to"   => to   = Some(value("--to")?.parse::<Format>()?),
+            "--bad-records" => {
+                strictness = parse_strictness(&value("--bad-records")?)?;
+            }
+            "--resample" => {
+                let rate = value("--resample")?;
+                resample = Some(rate.parse().ok()
+                    .filter(|x: &f64| x.is_finite() && *x > 0.)
+                    .ok_or_else(|| format!("Invalid rate {:?}", rate))?);
+            }
+            "--smooth" => mode = Interpolation::CatmullRom,
+            _ if arg.starts_with('-') => {
+                return Err(format!("Unknown option {}", arg).into());
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let (input, output) = match &paths[..] {
+        [input, output] => (input, output),
+        _ => return Err("convert needs an input and an output file".into()),
+    };
+
+    // `.lua` is ambiguous, but we only write the addon's format if asked to
+    let to = to.or_else(|| Format::from_extension(output))
+        .or_else(|| {
+            output.extension().filter(|x| x.eq_ignore_ascii_case("lua"))
+                .map(|_| Format::LuaTable)
+        })
+        .ok_or("Can't tell the output format from its extension, use --to")?;
+
+    let mut positions = load(input, from, strictness)?;
+    if let Some(rate) = resample {
+        positions = positions.resample(rate, mode)
+            .ok_or("Resampling gives too many positions, use a lower rate")?;
+    }
+    positions.save(output, to)?;
+    print!("Wrote {} positions to {} as {}\n",
+           positions.positions.len(), output.display(), to.name());
+    Ok(())
+}
+
+/// Quote `value` as a JSON string
+fn json_string(value: &str) -> String {
+    let mut ret = String::from("\"");
+    for chr in value.chars() {
+        match chr {
+            '"'  => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            _ if (chr as u32) < 0x20 => {
+                ret.push_str(&format!("\\u{:04x}", chr as u32));
+            }
+            _ => ret.push(chr),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Print the analyses of `recordings` as a table
+fn print_table(recordings: &[(PathBuf, Analysis)], events: bool) {
+    print!("{:<24} {:>7} {:>4} {:>9} {:>10} {:>7} {:>7} {:>8} {:>8} \
+            {:>5} {:>5} {:>5} {:>8}\n",
+           "file", "samples", "segs", "time (s)", "dist (yd)", "avg",
+           "max", "climb", "descent", "jumps", "falls", "rides",
+           "ride (s)");
+    for (path, analysis) in recordings {
+        let s = &analysis.summary;
+        print!("{:<24} {:>7} {:>4} {:>9.1} {:>10.1} {:>7.2} {:>7.2} \
+                {:>8.1} {:>8.1} {:>5} {:>5} {:>5} {:>8.1}\n",
+               path.display().to_string(), s.samples, s.segments,
+               s.duration, s.distance, s.average_speed, s.max_speed, s.climb,
+               s.descent, s.jumps, s.falls, s.rides, s.ride_time);
+    }
+
+    if !events {
+        return;
+    }
+    for (path, analysis) in recordings {
+        print!("\n{}\n", path.display());
+        for event in &analysis.events {
+            let unit = match event.kind {
+                EventKind::Ride => "yd/s",
+                _ => "yd",
+            };
+            print!("    {:<4} samples {:>6}-{:<6} {:>7.2} s {:>7.2} {}\n",
+                   event.kind.name(), event.start, event.end,
+                   event.duration, event.magnitude, unit);
+        }
+    }
+}
+
+/// Print the analyses of `recordings` as a JSON array
+fn print_json(recordings: &[(PathBuf, Analysis)], events: bool) {
+    print!("[\n");
+    for (ii, (path, analysis)) in recordings.iter().enumerate() {
+        let s = &analysis.summary;
+        print!("  {{\"file\":{},\"samples\":{},\"segments\":{},\
+                \"duration\":{},\"distance\":{},\"average_speed\":{},\
+                \"max_speed\":{},\"climb\":{},\"descent\":{},\
+                \"jumps\":{},\"falls\":{},\"rides\":{},\"ride_time\":{}",
+               json_string(&path.display().to_string()), s.samples,
+               s.segments, s.duration, s.distance, s.average_speed,
+               s.max_speed, s.climb, s.descent, s.jumps, s.falls, s.rides,
+               s.ride_time);
+        if events {
+            let list = analysis.events.iter().map(|x| {
+                format!("{{\"kind\":\"{}\",\"start\":{},\"end\":{},\
+                         \"duration\":{},\"magnitude\":{}}}",
+                        x.kind.name(), x.start, x.end, x.duration,
+                        x.magnitude)
+            }).collect::<Vec<_>>().join(",");
+            print!(",\"events\":[{}]", list);
+        }
+        print!("}}{}\n", if ii + 1 < recordings.len() { "," } else { "" });
+    }
+    print!("]\n");
+}
+
+/// The `analyze` command
+fn analyze<I: Iterator<Item = String>>(mut args: I)
+        -> Result<(), Box<dyn Error>> {
+    let mut json       = false;
+    let mut events     = false;
+    let mut strictness = Strictness::default();
+    let mut options    = SegmentOptions::default();
+    let mut paths      = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next().ok_or_else(|| format!("{} requires a value", name))
+        };
+
+        match arg.as_str() {
+            "--json"   => json = true,
+            "--events" => events = true,
+            "--bad-records" => {
+                strictness = parse_strictness(&value("--bad-records")?)?;
+            }
+            "--gap" => {
+                let gap = value("--gap")?;
+                options.gap_threshold = gap.parse().ok()
+                    .filter(|x: &f64| *x > 0.)
+                    .ok_or_else(|| format!("Invalid gap {:?}", gap))?;
+            }
+            "--teleport" => {
+                let distance = value("--teleport")?;
+                options.teleport_distance = distance.parse().ok()
+                    .filter(|x: &f64| *x > 0.)
+                    .ok_or_else(|| format!("Invalid distance {:?}",
+                                           distance))?;
+            }
+            _ if arg.starts_with('-') => {
+                return Err(format!("Unknown option {}", arg).into());
+            }
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    if paths.is_empty() {
+        return Err("analyze needs at least one input file".into());
+    }
+
+    let mut recordings = Vec::new();
+    for path in paths {
+        let analysis = load(&path, None, strictness)?.analyze(&options);
+        recordings.push((path, analysis));
+    }
+
+    if json {
+        print_json(&recordings, events);
+    } else {
+        print_table(&recordings, events);
+    }
+    Ok(())
+}
+
+/// Parse the arguments and run the requested command
+fn run() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args();
+    let prog = args.next().unwrap_or_else(|| "parse_ealogpos".into());
+
+    match args.next().as_deref() {
+        Some("convert") => convert(args),
+        Some("analyze") => analyze(args),
+        Some("help") | Some("-h") | Some("--help") => {
+            print!("{}", usage(&prog));
+            Ok(())
+        }
+        Some(cmd) => Err(format!("Unknown command {}\n\n{}",
+                                 cmd, usage(&prog)).into()),
+        None => Err(usage(&prog).into()),
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprint!("{}\n", err);
+        std::process::exit(1);
+    }
+}
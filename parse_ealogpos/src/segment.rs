@@ -0,0 +1,164 @@
+//! Splitting of recordings into continuous segments
+
+use crate::{Position, Positions};
+
+/// Why a segment ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Split {
+    /// The next sample is on a different map
+    MapChange,
+
+    /// The next sample is further away than a teleport threshold
+    Teleport,
+
+    /// The next sample is further away in time than the gap threshold
+    Gap,
+}
+
+/// When to split a recording into segments
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentOptions {
+    /// Split when consecutive samples are more than this many seconds apart
+    pub gap_threshold: f64,
+
+    /// Split when consecutive samples are more than this far apart
+    /// horizontally
+    pub teleport_distance: f64,
+}
+
+impl Default for SegmentOptions {
+    fn default() -> Self {
+        SegmentOptions {
+            gap_threshold:     crate::DEFAULT_GAP_THRESHOLD,
+            teleport_distance: 10.,
+        }
+    }
+}
+
+/// A continuous part of a recording, on one map without teleports or gaps
+#[derive(Debug, Clone, Copy)]
+pub struct Segment<'a> {
+    /// Index of the first sample of the segment in the recording
+    pub start: usize,
+
+    /// The samples in the segment, there is always at least one
+    pub positions: &'a [Position],
+
+    /// Why the segment ended, `None` for the last one
+    pub split: Option<Split>,
+
+    /// Time from the first to the last sample in seconds
+    pub duration: f64,
+
+    /// Distance travelled along the samples
+    pub length: f64,
+}
+
+impl Segment<'_> {
+    /// Map the segment is on
+    pub fn map_id(&self) -> u32 {
+        self.positions[0].map_id
+    }
+
+    /// Average speed over the segment, 0 if it has no duration
+    pub fn average_speed(&self) -> f64 {
+        if self.duration > 0. {
+            self.length / self.duration
+        } else {
+            0.
+        }
+    }
+}
+
+/// Get the distance between `a` and `b`, ignoring height if `horizontal`
+fn distance(a: &Position, b: &Position, horizontal: bool) -> f64 {
+    let z = if horizontal { 0. } else { b.z - a.z };
+    ((b.x - a.x).powi(2) + (b.y - a.y).powi(2) + z.powi(2)).sqrt()
+}
+
+/// Find why the recording would be split between `a` and `b`, if at all
+fn split(a: &Position, b: &Position, options: &SegmentOptions)
+        -> Option<Split> {
+    if a.map_id != b.map_id {
+        Some(Split::MapChange)
+    } else if b.time - a.time > options.gap_threshold {
+        Some(Split::Gap)
+    } else if distance(a, b, true) > options.teleport_distance {
+        Some(Split::Teleport)
+    } else {
+        None
+    }
+}
+
+impl Positions {
+    /// Split the recording into continuous segments according to `options`
+    pub fn segments(&self, options: &SegmentOptions) -> Vec<Segment<'_>> {
+        let positions = &self.positions;
+        let mut segments = Vec::new();
+
+        let mut start = 0;
+        let mut length = 0.;
+        for ii in 0..positions.len() {
+            let next = positions.get(ii + 1);
+            let reason = next.and_then(|x| split(&positions[ii], x, options));
+            if next.is_some() && reason.is_none() {
+                length += distance(&positions[ii], &positions[ii + 1], false);
+                continue;
+            }
+
+            segments.push(Segment {
+                start,
+                positions: &positions[start..=ii],
+                split: reason,
+                duration: positions[ii].time - positions[start].time,
+                length,
+            });
+            start  = ii + 1;
+            length = 0.;
+        }
+
+        segments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Strictness;
+
+    #[test]
+    fn recordings_are_split() {
+        let data = "{
+            \"LOCATION237945_0_0_0_0_0_100\",
+            \"LOCATION237945_3_4_0_0_0_101\",
+            \"LOCATION237945_3_4_0_1_0_102\",
+            \"LOCATION237945_3_4_0_1_0_110\",
+            \"LOCATION237945_3_4_12_1_0_111\",
+            \"LOCATION237945_50_4_12_1_0_112\",
+        }";
+        let (positions, _) =
+            Positions::parse_lua(data, Strictness::Strict).unwrap();
+        let segments = positions.segments(&SegmentOptions::default());
+
+        assert_eq!(segments.iter()
+                   .map(|x| (x.start, x.positions.len(), x.split))
+                   .collect::<Vec<_>>(), vec![
+            (0, 2, Some(Split::MapChange)),
+            (2, 1, Some(Split::Gap)),
+            (3, 2, Some(Split::Teleport)),
+            (5, 1, None),
+        ]);
+
+        assert_eq!(segments[0].map_id(), 0);
+        assert_eq!(segments[0].length, 5.);
+        assert_eq!(segments[0].average_speed(), 5.);
+        assert_eq!(segments[1].average_speed(), 0.);
+        assert_eq!((segments[2].duration, segments[2].length), (1., 12.));
+    }
+
+    #[test]
+    fn empty_recordings_have_no_segments() {
+        let positions = Positions { positions: Vec::new() };
+        assert!(positions.segments(&SegmentOptions::default()).is_empty());
+    }
+}
@@ -0,0 +1,321 @@
+//! Finding where a recording passes closest to a target, and the heading to
+//! jump towards it from there
+//!
+//! Distances are horizontal, as that is what decides whether a jump from a
+//! moving platform can make it, and the height difference is reported
+//! separately. The path is treated as straight lines between samples in the
+//! same segment.
+
+use crate::{Position, Segment};
+
+/// Something to reach, in WoW coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// A single point
+    Point([f64; 3]),
+
+    /// Anywhere on a triangle, eg. one picked from the mesh
+    Triangle([[f64; 3]; 3]),
+}
+
+/// The point of a recording closest to a target
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Approach {
+    /// Index of the sample starting the line the closest point is on
+    pub index: usize,
+
+    /// Interpolated position of the closest point on the path
+    pub position: Position,
+
+    /// Closest point on the target
+    pub target: [f64; 3],
+
+    /// Horizontal distance from `position` to `target`
+    pub distance: f64,
+
+    /// Height of `target` above `position`
+    pub height: f64,
+
+    /// Heading to jump at in degrees, in the convention of `Position::angle`
+    pub heading: f64,
+
+    /// Heading of the platform the path was recorded on at `position`
+    pub platform_heading: Option<f64>,
+
+    /// `heading` relative to `platform_heading`, from 0 to 360
+    pub relative_heading: Option<f64>,
+}
+
+/// Where the heading of the platform a path was recorded on comes from
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Platform {
+    /// The direction the path is travelling in
+    #[default]
+    Travel,
+
+    /// The recorded facing, minus this many degrees. Use this when the
+    /// character faced a fixed way relative to the platform while recording,
+    /// eg. standing still on a boat.
+    Facing(f64),
+}
+
+/// A horizontal point
+type Point2 = [f64; 2];
+
+fn sub(a: Point2, b: Point2) -> Point2 {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn cross(a: Point2, b: Point2) -> f64 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+fn length(a: Point2) -> f64 {
+    (a[0] * a[0] + a[1] * a[1]).sqrt()
+}
+
+/// Get the fraction along `a` to `b` of the point closest to `point`
+fn project(point: Point2, a: Point2, b: Point2) -> f64 {
+    let ab  = sub(b, a);
+    let len = ab[0] * ab[0] + ab[1] * ab[1];
+    if len == 0. {
+        return 0.;
+    }
+    let ap = sub(point, a);
+    ((ap[0] * ab[0] + ap[1] * ab[1]) / len).clamp(0., 1.)
+}
+
+/// Linearly interpolate from `a` to `b`
+fn lerp<const N: usize>(a: [f64; N], b: [f64; N], t: f64) -> [f64; N] {
+    let mut ret = a;
+    for (ret, b) in ret.iter_mut().zip(b.iter()) {
+        *ret += (b - *ret) * t;
+    }
+    ret
+}
+
+/// Get the fractions along lines `a` and `b` of their closest points
+fn closest_lines(a: [Point2; 2], b: [Point2; 2]) -> (f64, f64) {
+    // Where they cross, if they do
+    let da    = sub(a[1], a[0]);
+    let db    = sub(b[1], b[0]);
+    let denom = cross(da, db);
+    if denom != 0. {
+        let ab = sub(b[0], a[0]);
+        let ta = cross(ab, db) / denom;
+        let tb = cross(ab, da) / denom;
+        if (0. ..=1.).contains(&ta) && (0. ..=1.).contains(&tb) {
+            return (ta, tb);
+        }
+    }
+
+    // Otherwise one of the ends is closest
+    let candidates = [
+        (0., project(a[0], b[0], b[1])),
+        (1., project(a[1], b[0], b[1])),
+        (project(b[0], a[0], a[1]), 0.),
+        (project(b[1], a[0], a[1]), 1.),
+    ];
+    let dist = |&(ta, tb): &(f64, f64)| {
+        length(sub(lerp(a[0], a[1], ta), lerp(b[0], b[1], tb)))
+    };
+    candidates.iter().copied()
+        .min_by(|x, y| dist(x).total_cmp(&dist(y)))
+        .unwrap()
+}
+
+/// Check if `point` is inside the horizontal triangle `tri`
+fn inside(point: Point2, tri: [Point2; 3]) -> bool {
+    let sides = [
+        cross(sub(tri[1], tri[0]), sub(point, tri[0])),
+        cross(sub(tri[2], tri[1]), sub(point, tri[1])),
+        cross(sub(tri[0], tri[2]), sub(point, tri[2])),
+    ];
+    sides.iter().all(|&x| x >= 0.) || sides.iter().all(|&x| x <= 0.)
+}
+
+/// Get the height of the plane through `tri` at `point`, falling back to the
+/// average height for vertical triangles
+fn height_at(point: Point2, tri: &[[f64; 3]; 3]) -> f64 {
+    let flat = |x: &[f64; 3]| [x[0], x[1]];
+    let (a, b, c) = (flat(&tri[0]), flat(&tri[1]), flat(&tri[2]));
+    let area = cross(sub(b, a), sub(c, a));
+    if area == 0. {
+        return (tri[0][2] + tri[1][2] + tri[2][2]) / 3.;
+    }
+
+    let wb = cross(sub(point, a), sub(c, a)) / area;
+    let wc = cross(sub(b, a), sub(point, a)) / area;
+    let wa = 1. - wb - wc;
+    wa * tri[0][2] + wb * tri[1][2] + wc * tri[2][2]
+}
+
+impl Target {
+    /// Get the fraction along the line from `a` to `b` closest to the
+    /// target, and the closest point on the target
+    fn closest(&self, a: Point2, b: Point2) -> (f64, [f64; 3]) {
+        match self {
+            Target::Point(point) => {
+                (project([point[0], point[1]], a, b), *point)
+            }
+            Target::Triangle(tri) => {
+                let flat = [
+                    [tri[0][0], tri[0][1]],
+                    [tri[1][0], tri[1][1]],
+                    [tri[2][0], tri[2][1]],
+                ];
+                let on_target = |t: f64, point: Point2| {
+                    (t, [point[0], point[1], height_at(point, tri)])
+                };
+                if inside(a, flat) {
+                    return on_target(0., a);
+                }
+
+                // Crossing into the triangle goes through an edge, so
+                // otherwise the closest point is on one
+                let mut best = (f64::MAX, 0., a);
+                for edge in 0..3 {
+                    let edge = [flat[edge], flat[(edge + 1) % 3]];
+                    let (t, te) = closest_lines([a, b], edge);
+                    let point = lerp(edge[0], edge[1], te);
+                    let dist  = length(sub(lerp(a, b, t), point));
+                    if dist < best.0 {
+                        best = (dist, t, point);
+                    }
+                }
+                on_target(best.1, best.2)
+            }
+        }
+    }
+
+    /// Get the horizontal distance from `x`, `y` to the closest point of the
+    /// target
+    pub fn distance(&self, x: f64, y: f64) -> f64 {
+        let (_, target) = self.closest([x, y], [x, y]);
+        length(sub([target[0], target[1]], [x, y]))
+    }
+
+    /// Find where the recording in `segments` passes closest to the target.
+    /// The heading of the platform it was recorded on comes from `platform`.
+    pub fn closest_approach<'a, 'b: 'a, I>(&self, segments: I,
+                                           platform: Platform)
+            -> Option<Approach>
+            where I: IntoIterator<Item = &'a Segment<'b>> {
+        let mut best: Option<Approach> = None;
+        for segment in segments {
+            // Single samples are a line to themselves
+            let positions = segment.positions;
+            let last  = positions.len() - 1;
+            let pairs = (0..last.max(1)).map(|ii| {
+                (ii, &positions[ii], &positions[(ii + 1).min(last)])
+            });
+
+            for (ii, a, b) in pairs {
+                let (t, target) = self.closest([a.x, a.y], [b.x, b.y]);
+                let [x, y, z] = lerp([a.x, a.y, a.z], [b.x, b.y, b.z], t);
+                let distance = length(sub([target[0], target[1]], [x, y]));
+                if best.map(|x| distance >= x.distance).unwrap_or(false) {
+                    continue;
+                }
+
+                let position = Position {
+                    time: a.time + (b.time - a.time) * t,
+                    x, y, z,
+                    map_id: a.map_id,
+                    angle: a.angle,
+                };
+                let heading = (target[1] - y).atan2(target[0] - x)
+                    .to_degrees().rem_euclid(360.);
+                let platform_heading = match platform {
+                    Platform::Facing(offset) =>
+                        Some((a.angle - offset).rem_euclid(360.)),
+                    Platform::Travel if (b.x, b.y) != (a.x, a.y) => Some(
+                        (b.y - a.y).atan2(b.x - a.x).to_degrees()
+                            .rem_euclid(360.)),
+                    Platform::Travel => None,
+                };
+                best = Some(Approach {
+                    index: segment.start + ii,
+                    position,
+                    target,
+                    distance,
+                    height: target[2] - z,
+                    heading,
+                    platform_heading,
+                    relative_heading: platform_heading
+                        .map(|x| (heading - x).rem_euclid(360.)),
+                });
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Positions, SegmentOptions};
+
+    /// Build a recording along `(x, y, z, angle)` samples a second apart
+    fn recording_per_second(samples: &[(f64, f64, f64, f64)]) -> Positions {
+        Positions { positions: samples.iter().enumerate()
+            .map(|(ii, &(x, y, z, angle))| Position {
+                time: ii as f64, x, y, z, map_id: 0, angle,
+            }).collect() }
+    }
+
+    #[test]
+    fn closest_point_on_path() {
+        let positions = recording_per_second(&[
+            (0., 0., 0., 90.),
+            (8., 0., 2., 90.),
+            (16., 0., 4., 90.),
+        ]);
+        let segments = positions.segments(&SegmentOptions::default());
+
+        // Off to the +y side of the middle of the second line
+        let approach = Target::Point([12., 5., 10.])
+            .closest_approach(&segments, Platform::Travel).unwrap();
+        assert_eq!(approach.index, 1);
+        assert_eq!((approach.position.x, approach.position.z), (12., 3.));
+        assert_eq!(approach.position.time, 1.5);
+        assert_eq!((approach.distance, approach.height), (5., 7.));
+        assert_eq!(approach.heading, 90.);
+        assert_eq!(approach.relative_heading, Some(90.));
+
+        // Platform facing 90 degrees off the recorded angle
+        let approach = Target::Point([-3., -4., 0.])
+            .closest_approach(&segments, Platform::Facing(45.)).unwrap();
+        assert_eq!((approach.index, approach.distance), (0, 5.));
+        assert_eq!(approach.platform_heading, Some(45.));
+        let expected = (-4f64).atan2(-3.).to_degrees().rem_euclid(360.);
+        assert_eq!(approach.heading, expected);
+        assert_eq!(approach.relative_heading, Some(expected - 45.));
+    }
+
+    #[test]
+    fn closest_point_on_triangle() {
+        let positions = recording_per_second(&[
+            (0., 0., 0., 0.),
+            (8., 0., 0., 0.),
+        ]);
+        let segments = positions.segments(&SegmentOptions::default());
+
+        // Path runs through the triangle
+        let tri = Target::Triangle([[4., -1., 1.], [6., 1., 3.], [2., 1., 3.]]);
+        let approach = tri.closest_approach(&segments, Platform::Travel)
+            .unwrap();
+        assert_eq!(approach.distance, 0.);
+        assert_eq!(approach.height, 2.);
+        assert_eq!(tri.distance(4., 0.), 0.);
+        assert_eq!(tri.distance(4., -4.), 3.);
+
+        // Triangle off to the side, closest at its bottom vertex
+        let tri = Target::Triangle([[4., 3., 1.], [6., 5., 3.], [2., 5., 3.]]);
+        let approach = tri.closest_approach(&segments, Platform::Travel)
+            .unwrap();
+        assert_eq!((approach.position.x, approach.distance), (4., 3.));
+        assert!((approach.target[2] - 1.).abs() < 1e-9);
+        assert_eq!(approach.heading, 90.);
+    }
+}
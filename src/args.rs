@@ -0,0 +1,317 @@
+//! Command line argument parsing for the viewer
+
+use std::path::PathBuf;
+
+use parse_ealogpos::{Strictness, Target, Platform};
+
+use crate::colors::{ColorBy, Colormap, COLOR_BYS, COLORMAPS};
+use crate::slopes;
+
+/// Usage text, `{}` is replaced with the program name
+const USAGE: &str = "\
+Usage: {} [options] <falkvbo file>
+
+Options:
+    --path <file>[:color]  Overlay a recorded path from a position log, in
+                           any format `parse_ealogpos` reads. `color` is the
+                           line intensity from 0.0 to 1.0. If omitted, the
+                           path is coloured according to --color. May be
+                           repeated.
+    --bad-records <mode>   What to do with malformed records in path files:
+                           `fail` to exit, `warn` to skip them with a
+                           warning (the default), or `skip` to skip them
+                           silently
+    --gap <seconds>        Break paths where samples are further apart in
+                           time than this, defaults to 5
+    --map-id <id>          Only draw path samples recorded on this map id
+    --start-pos <x,y,z>    Initial camera position in WoW coordinates
+    --target <x,y,z>       Find where each path comes closest to this point,
+                           or to a triangle given as 9 comma separated
+                           coordinates, and the heading to jump at from there
+    --platform-offset <deg>
+                           Take the heading of the platform the paths were
+                           recorded on to be the recorded facing minus this,
+                           rather than the direction of travel
+    --color <mode>         What to colour paths by: {color_bys}. Defaults
+                           to distance with a target, time otherwise. `c`
+                           cycles through them in the viewer.
+    --colormap <name>      How to colour values: {colormaps}. Defaults to
+                           viridis, `v` cycles through them in the viewer.
+    --slopes <file>        Load more slope presets from a settings file,
+                           `r` reloads it in the viewer
+    --slope-preset <name>  Slope thresholds and colours to start with:
+                           {slope_presets}, or one from --slopes.
+                           Defaults to wow, `b` cycles through them in the
+                           viewer.
+    --check                Validate the falkvbo file, print a report and exit
+    --help                 Print this message
+";
+
+/// A path overlay requested on the command line
+#[derive(Debug, Clone)]
+pub struct PathArg {
+    /// File containing the recorded positions
+    pub filename: PathBuf,
+
+    /// Solid line intensity for the path, if one was specified
+    pub color: Option<f32>,
+}
+
+/// Parsed command line arguments
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    /// The falkvbo mesh to display
+    pub mesh: PathBuf,
+
+    /// Recorded paths to overlay on the mesh
+    pub paths: Vec<PathArg>,
+
+    /// How malformed records in the path files are handled
+    pub strictness: Strictness,
+
+    /// Time between samples in seconds above which paths are broken, the
+    /// library default if not set
+    pub gap_threshold: Option<f64>,
+
+    /// If set, only path samples on this map are drawn
+    pub map_id: Option<u32>,
+
+    /// Initial camera position in WoW coordinates
+    pub start_pos: Option<(f32, f32, f32)>,
+
+    /// Target to find the closest approach of each path to
+    pub target: Option<Target>,
+
+    /// Where the heading of the platform paths were recorded on comes from
+    pub platform: Platform,
+
+    /// What paths are coloured by, picked from the other options if not set
+    pub color_by: Option<ColorBy>,
+
+    /// How path values are turned into colours
+    pub colormap: Colormap,
+
+    /// Settings file with more slope presets
+    pub slope_file: Option<PathBuf>,
+
+    /// Name of the slope preset to start with, the default if not set
+    pub slope_preset: Option<String>,
+
+    /// Only validate the mesh rather than displaying it
+    pub check: bool,
+}
+
+/// Result of parsing the command line
+pub enum Command {
+    /// Run the viewer with these arguments
+    Run(Box<Args>),
+
+    /// Print the usage and exit successfully
+    Help,
+}
+
+/// Get the usage text for the program named `prog`
+pub fn usage(prog: &str) -> String {
+    let names = |names: Vec<&str>| names.join(", ");
+    USAGE.replacen("{}", prog, 1)
+        .replace("{color_bys}", &names(COLOR_BYS.iter().map(|x| x.name())
+                                       .collect()))
+        .replace("{colormaps}", &names(COLORMAPS.iter().map(|x| x.name())
+                                       .collect()))
+        .replace("{slope_presets}", &names(slopes::builtin().iter()
+                                           .map(|x| x.name.as_str())
+                                           .collect()))
+}
+
+/// Parse a `--path` argument of the form `<file>[:color]`
+fn parse_path(arg: &str) -> Result<PathArg, String> {
+    // Only treat the text after the last `:` as a colour if it parses as
+    // one, this way Windows drive letters and odd filenames still work
+    if let Some(idx) = arg.rfind(':') {
+        if let Ok(color) = arg[idx + 1..].parse::<f32>() {
+            if !(0.0..=1.0).contains(&color) {
+                return Err(format!(
+                    "Path colour must be between 0.0 and 1.0, got {}", color));
+            }
+
+            return Ok(PathArg {
+                filename: PathBuf::from(&arg[..idx]),
+                color:    Some(color),
+            });
+        }
+    }
+
+    Ok(PathArg {
+        filename: PathBuf::from(arg),
+        color:    None,
+    })
+}
+
+/// Parse a comma separated `x,y,z` triple
+fn parse_vec3(arg: &str) -> Result<(f32, f32, f32), String> {
+    let parts: Vec<&str> = arg.split(',').map(|x| x.trim()).collect();
+    if parts.len() != 3 {
+        return Err(format!("Expected x,y,z but got {:?}", arg));
+    }
+
+    let parse = |x: &str| {
+        x.parse::<f32>().ok().filter(|x| x.is_finite())
+            .ok_or_else(|| format!("Invalid coordinate {:?}", x))
+    };
+    Ok((parse(parts[0])?, parse(parts[1])?, parse(parts[2])?))
+}
+
+/// Parse a `--target` of either a point or the three points of a triangle,
+/// as comma separated `x,y,z` coordinates
+fn parse_target(arg: &str) -> Result<Target, String> {
+    let coords = arg.split(',').map(|x| {
+        x.trim().parse::<f64>().ok().filter(|x| x.is_finite())
+            .ok_or_else(|| format!("Invalid coordinate {:?}", x))
+    }).collect::<Result<Vec<f64>, String>>()?;
+
+    match coords[..] {
+        [x, y, z] => Ok(Target::Point([x, y, z])),
+        [ax, ay, az, bx, by, bz, cx, cy, cz] => Ok(Target::Triangle(
+            [[ax, ay, az], [bx, by, bz], [cx, cy, cz]])),
+        _ => Err(format!("Expected a point or a triangle but got {:?}", arg)),
+    }
+}
+
+/// Parse the command line arguments, not including the program name
+pub fn parse<I: IntoIterator<Item = String>>(args: I)
+        -> Result<Command, String> {
+    let mut ret  = Args::default();
+    let mut mesh = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // Get the value for an option which requires one
+        let mut value = |name: &str| {
+            args.next().ok_or_else(|| format!("{} requires a value", name))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--path" => {
+                ret.paths.push(parse_path(&value("--path")?)?);
+            }
+            "--bad-records" => {
+                let mode = value("--bad-records")?;
+                ret.strictness = match mode.as_str() {
+                    "fail" => Strictness::Strict,
+                    "warn" => Strictness::Collect,
+                    "skip" => Strictness::Skip,
+                    _ => return Err(format!(
+                        "Invalid --bad-records mode {:?}", mode)),
+                };
+            }
+            "--gap" => {
+                let gap = value("--gap")?;
+                ret.gap_threshold = Some(gap.parse().ok()
+                    .filter(|x: &f64| *x > 0.)
+                    .ok_or_else(|| format!("Invalid gap {:?}", gap))?);
+            }
+            "--map-id" => {
+                let map_id = value("--map-id")?;
+                ret.map_id = Some(map_id.parse().map_err(|_| {
+                    format!("Invalid map id {:?}", map_id)
+                })?);
+            }
+            "--start-pos" => {
+                ret.start_pos = Some(parse_vec3(&value("--start-pos")?)?);
+            }
+            "--target" => {
+                ret.target = Some(parse_target(&value("--target")?)?);
+            }
+            "--platform-offset" => {
+                let offset = value("--platform-offset")?;
+                ret.platform = Platform::Facing(offset.parse().ok()
+                    .filter(|x: &f64| x.is_finite())
+                    .ok_or_else(|| format!("Invalid angle {:?}", offset))?);
+            }
+            "--color" => ret.color_by = Some(value("--color")?.parse()?),
+            "--colormap" => ret.colormap = value("--colormap")?.parse()?,
+            "--slopes" => {
+                ret.slope_file = Some(PathBuf::from(value("--slopes")?));
+            }
+            "--slope-preset" => {
+                ret.slope_preset = Some(value("--slope-preset")?);
+            }
+            "--check" => ret.check = true,
+            _ if arg.starts_with('-') => {
+                return Err(format!("Unknown option {}", arg));
+            }
+            _ => {
+                if mesh.is_some() {
+                    return Err(format!("Unexpected argument {}", arg));
+                }
+                mesh = Some(PathBuf::from(arg));
+            }
+        }
+    }
+
+    ret.mesh = mesh.ok_or("Missing falkvbo file")?;
+    Ok(Command::Run(Box::new(ret)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse `args`, which must be for running the viewer
+    fn run(args: &[&str]) -> Result<Args, String> {
+        match parse(args.iter().map(|x| x.to_string()))? {
+            Command::Run(args) => Ok(*args),
+            Command::Help => panic!("Got help for {:?}", args),
+        }
+    }
+
+    #[test]
+    fn paths_and_colours() {
+        let args = run(&["--path", "a.csv:0.5", "--path", "C:\\x.lua",
+                         "--path", "dir:name.lua", "mesh.falkvbo"]).unwrap();
+        assert_eq!(args.mesh, PathBuf::from("mesh.falkvbo"));
+        assert_eq!(args.paths.iter().map(|x| (x.filename.to_str().unwrap(),
+                                              x.color))
+                   .collect::<Vec<_>>(), vec![
+            ("a.csv", Some(0.5)),
+            ("C:\\x.lua", None),
+            ("dir:name.lua", None),
+        ]);
+
+        assert_eq!(run(&["--path", "a.csv:1.5", "m"]).unwrap_err(),
+                   "Path colour must be between 0.0 and 1.0, got 1.5");
+    }
+
+    #[test]
+    fn values_are_checked() {
+        let args = run(&["m", "--start-pos", "1, 2,3", "--target", "4,5,6",
+                         "--platform-offset", "90"]).unwrap();
+        assert_eq!(args.start_pos, Some((1., 2., 3.)));
+        assert_eq!(args.target, Some(Target::Point([4., 5., 6.])));
+        assert_eq!(args.platform, Platform::Facing(90.));
+
+        for &(option, value, err) in &[
+            ("--start-pos", "nan,0,0", "Invalid coordinate \"nan\""),
+            ("--start-pos", "0,inf,0", "Invalid coordinate \"inf\""),
+            ("--start-pos", "0,0", "Expected x,y,z but got \"0,0\""),
+            ("--target", "0,0,-inf", "Invalid coordinate \"-inf\""),
+            ("--platform-offset", "NaN", "Invalid angle \"NaN\""),
+            ("--gap", "0", "Invalid gap \"0\""),
+            ("--bad-records", "all", "Invalid --bad-records mode \"all\""),
+        ] {
+            assert_eq!(run(&["m", option, value]).unwrap_err(), err);
+        }
+    }
+
+    #[test]
+    fn bad_command_lines_are_errors() {
+        assert_eq!(run(&["m", "--target"]).unwrap_err(),
+                   "--target requires a value");
+        assert_eq!(run(&["m", "--nope"]).unwrap_err(), "Unknown option --nope");
+        assert_eq!(run(&["a", "b"]).unwrap_err(), "Unexpected argument b");
+        assert_eq!(run(&["--check"]).unwrap_err(), "Missing falkvbo file");
+        assert!(matches!(parse(vec!["m".into(), "--help".into()]),
+                         Ok(Command::Help)));
+    }
+}
@@ -0,0 +1,334 @@
+//! The viewer's camera, which can fly freely, orbit a point or look straight
+//! down at a map
+//!
+//! All positions are in GL coordinates, where y is up and the camera's yaw
+//! is around it.
+
+use cgmath::{Matrix4, Point3, Vector3, Vector4, Deg, InnerSpace, SquareMatrix};
+use cgmath::{ortho, perspective};
+
+/// Vertical field of view of the perspective modes, in degrees
+const FOV: f32 = 45.;
+
+/// Furthest the camera looks up or down from level, in radians. Looking
+/// straight up or down leaves the view's up direction undefined.
+const PITCH_LIMIT: f32 = std::f32::consts::FRAC_PI_2 - 0.0001;
+
+/// Near and far clip planes
+const NEAR: f32 = 0.01;
+const FAR:  f32 = 2000000.;
+
+/// Height the top-down view looks from, above anything on a map
+const TOP_DOWN_HEIGHT: f32 = 100000.;
+
+/// Distance to orbit at when there's nothing under the crosshair
+const DEFAULT_ORBIT_DISTANCE: f32 = 100.;
+
+/// Radians the camera turns per pixel of mouse movement
+const MOUSE_SENSITIVITY: f32 = 1. / 400.;
+
+/// Factor the mouse wheel zooms by per step
+const ZOOM_STEP: f32 = 1.2;
+
+/// How the camera is moved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Fly around, looking with the mouse
+    FreeFly,
+
+    /// Circle around a pivot point with the mouse
+    Orbit,
+
+    /// Look straight down with an orthographic projection, north up
+    TopDown,
+}
+
+impl Mode {
+    /// Name of the mode
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::FreeFly => "free-fly",
+            Mode::Orbit   => "orbit",
+            Mode::TopDown => "top-down",
+        }
+    }
+}
+
+/// The camera state for all modes, kept in step so switching between them
+/// keeps the view in place
+#[derive(Debug, Clone)]
+pub struct Camera {
+    /// Current mode
+    pub mode: Mode,
+
+    /// Position of the eye when flying, and the centre of the top-down view
+    pub position: Point3<f32>,
+
+    /// Angles of the view, in radians
+    pub yaw:   f32,
+    pub pitch: f32,
+
+    /// Point orbited around, and how far from it the eye is
+    pub pivot:    Point3<f32>,
+    pub distance: f32,
+
+    /// Half the height of the top-down view, in yards
+    pub zoom: f32,
+}
+
+/// Get the direction looked in for angles in radians
+pub fn look_direction(yaw: f32, pitch: f32) -> Vector3<f32> {
+    Vector3::new(pitch.cos() * yaw.sin(), pitch.sin(), pitch.cos() * yaw.cos())
+}
+
+impl Camera {
+    /// Create a camera looking straight down on `position` from above
+    pub fn new(position: Point3<f32>) -> Self {
+        Camera {
+            mode: Mode::TopDown,
+            position,
+            yaw: 0.,
+            pitch: -PITCH_LIMIT,
+            pivot: position,
+            distance: DEFAULT_ORBIT_DISTANCE,
+            zoom: position.y * (FOV / 2.).to_radians().tan(),
+        }
+    }
+
+    /// Direction the camera looks in
+    pub fn direction(&self) -> Vector3<f32> {
+        match self.mode {
+            Mode::TopDown => Vector3::new(0., -1., 0.),
+            _ => look_direction(self.yaw, self.pitch),
+        }
+    }
+
+    /// Position of the eye
+    pub fn eye(&self) -> Point3<f32> {
+        match self.mode {
+            Mode::FreeFly => self.position,
+            Mode::Orbit   => self.pivot - self.direction() * self.distance,
+            Mode::TopDown => Point3::new(self.position.x, TOP_DOWN_HEIGHT,
+                                         self.position.z),
+        }
+    }
+
+    /// Heading the camera faces in WoW's convention, degrees from the +x axis
+    /// towards +y. The top-down view always faces north.
+    pub fn heading(&self) -> f32 {
+        match self.mode {
+            Mode::TopDown => 0.,
+            _ => self.yaw.to_degrees().rem_euclid(360.),
+        }
+    }
+
+    /// Get the matrix transforming GL coordinates to clip space for a view
+    /// `aspect` times as wide as it is high
+    pub fn matrix(&self, aspect: f32) -> Matrix4<f32> {
+        let eye = self.eye();
+        match self.mode {
+            Mode::TopDown => {
+                // North, our +z, is up the screen
+                let proj = ortho(-self.zoom * aspect, self.zoom * aspect,
+                                 -self.zoom, self.zoom, NEAR, FAR);
+                proj * Matrix4::look_at(eye, eye + self.direction(),
+                                        Vector3::new(0., 0., 1.))
+            }
+            _ => {
+                let proj = perspective(Deg(FOV), aspect, NEAR, FAR);
+                proj * Matrix4::look_at(eye, eye + self.direction(),
+                                        Vector3::new(0., 1., 0.))
+            }
+        }
+    }
+
+    /// Get the origin and direction of the ray through the point `x`, `y` of
+    /// the view, both from -1 to 1 with y up, for a view `aspect` times as
+    /// wide as it is high
+    pub fn ray(&self, x: f32, y: f32, aspect: f32)
+            -> (Point3<f32>, Vector3<f32>) {
+        let inverse = self.matrix(aspect).invert()
+            .expect("Camera matrix not invertible");
+        let unproject = |z: f32| {
+            let point = inverse * Vector4::new(x, y, z, 1.);
+            Point3::new(point.x, point.y, point.z) / point.w
+        };
+        let near = unproject(-1.);
+        (near, (unproject(1.) - near).normalize())
+    }
+
+    /// Switch to `mode`, keeping the view where it is. `pick` finds the
+    /// distance to the mesh along a ray from a point, to pick the pivot to
+    /// orbit around.
+    pub fn set_mode<F>(&mut self, mode: Mode, pick: F)
+            where F: Fn(Point3<f32>, Vector3<f32>) -> Option<f32> {
+        if mode == self.mode {
+            return;
+        }
+
+        // Move everything to flying, then from there to the new mode
+        let eye = self.eye();
+        match self.mode {
+            Mode::FreeFly => {}
+            Mode::Orbit   => self.position = eye,
+            Mode::TopDown => {
+                // Look down from as high as needed to see as much
+                self.position.y = match pick(eye, self.direction()) {
+                    Some(dist) => eye.y - dist,
+                    None => 0.,
+                } + self.zoom / (FOV / 2.).to_radians().tan();
+            }
+        }
+        self.mode = Mode::FreeFly;
+
+        match mode {
+            Mode::FreeFly => {}
+            Mode::Orbit => {
+                self.distance = pick(self.position, self.direction())
+                    .unwrap_or(DEFAULT_ORBIT_DISTANCE);
+                self.pivot = self.position + self.direction() * self.distance;
+            }
+            Mode::TopDown => {
+                // Show as much as the perspective view does at the distance
+                // of what's being looked at
+                let dist = pick(self.position, self.direction())
+                    .unwrap_or(self.position.y.abs());
+                self.zoom = (dist * (FOV / 2.).to_radians().tan()).max(1.);
+            }
+        }
+        self.mode = mode;
+    }
+
+    /// Handle the mouse moving by `dx`, `dy` pixels in a view `height` pixels
+    /// high. This turns the flying and orbiting cameras, and pans the top-down
+    /// one.
+    pub fn mouse_motion(&mut self, dx: f32, dy: f32, height: f32) {
+        match self.mode {
+            Mode::FreeFly | Mode::Orbit => {
+                self.yaw  -= dx * MOUSE_SENSITIVITY;
+                self.pitch = (self.pitch - dy * MOUSE_SENSITIVITY)
+                    .clamp(-PITCH_LIMIT, PITCH_LIMIT);
+            }
+            Mode::TopDown => {
+                // Drag the map along with the mouse
+                let yards = 2. * self.zoom / height;
+                self.position.x += dx * yards;
+                self.position.z += dy * yards;
+            }
+        }
+    }
+
+    /// Handle a mouse wheel step, `up` if it's away from the user. This
+    /// changes `move_speed` when flying, and zooms otherwise.
+    pub fn wheel(&mut self, up: bool, move_speed: &mut f32) {
+        let factor = if up { ZOOM_STEP } else { 1. / ZOOM_STEP };
+        match self.mode {
+            Mode::FreeFly => *move_speed *= factor,
+            Mode::Orbit   => self.distance /= factor,
+            Mode::TopDown => self.zoom /= factor,
+        }
+    }
+
+    /// Move forward, to the left and up by these many yards. Orbiting moves
+    /// the pivot level with the ground, and top-down forward is north.
+    pub fn translate(&mut self, front: f32, strafe: f32, up: f32) {
+        let yaw = match self.mode {
+            Mode::TopDown => 0.,
+            _ => self.yaw,
+        };
+        let level  = Vector3::new(yaw.sin(), 0., yaw.cos());
+        let left   = look_direction(yaw + std::f32::consts::FRAC_PI_2, 0.);
+        let motion = left * strafe + Vector3::new(0., up, 0.);
+
+        match self.mode {
+            Mode::FreeFly => {
+                self.position += self.direction() * front + motion;
+            }
+            Mode::Orbit => self.pivot += level * front + motion,
+            Mode::TopDown => {
+                // Going up and down is the same as zooming
+                self.position += level * front + left * strafe;
+                self.zoom = (self.zoom - up).max(1.);
+            }
+        }
+    }
+
+    /// Keep `target` in view, from `distance` away in the perspective modes
+    pub fn follow(&mut self, target: Point3<f32>, distance: f32) {
+        match self.mode {
+            Mode::FreeFly => {
+                self.position = target - self.direction() * distance;
+            }
+            Mode::Orbit => self.pivot = target,
+            Mode::TopDown => self.position = target,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check that `a` and `b` are the same point, give or take float error
+    fn assert_close(a: Point3<f32>, b: Point3<f32>) {
+        assert!((a - b).magnitude() < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    /// Create a camera flying at `position`, looking down to the side
+    fn flying(position: Point3<f32>) -> Camera {
+        Camera {
+            mode: Mode::FreeFly,
+            yaw: 0.7,
+            pitch: -0.3,
+            ..Camera::new(position)
+        }
+    }
+
+    #[test]
+    fn orbiting_and_back_keeps_the_eye() {
+        let start = Point3::new(10., 50., -20.);
+        for &hit in &[Some(30.), None] {
+            let mut camera = flying(start);
+            camera.set_mode(Mode::Orbit, |_, _| hit);
+            assert_eq!(camera.distance, hit.unwrap_or(DEFAULT_ORBIT_DISTANCE));
+            assert_close(camera.eye(), start);
+
+            camera.set_mode(Mode::FreeFly, |_, _| panic!("Picked"));
+            assert_close(camera.eye(), start);
+            assert_eq!((camera.yaw, camera.pitch), (0.7, -0.3));
+        }
+    }
+
+    #[test]
+    fn top_down_keeps_the_map_position() {
+        let mut camera = flying(Point3::new(10., 50., -20.));
+        camera.set_mode(Mode::TopDown, |_, _| Some(40.));
+        let eye = camera.eye();
+        assert_eq!((eye.x, eye.z), (10., -20.));
+        assert_eq!(camera.zoom, 40. * (FOV / 2.).to_radians().tan());
+
+        camera.set_mode(Mode::FreeFly, |_, _| Some(TOP_DOWN_HEIGHT - 5.));
+        let eye = camera.eye();
+        assert_eq!((eye.x, eye.z), (10., -20.));
+    }
+
+    #[test]
+    fn leaving_top_down_flies_high_enough_to_see_as_much() {
+        let mut camera = Camera::new(Point3::new(10., 50., -20.));
+        camera.zoom = 100.;
+        camera.set_mode(Mode::FreeFly, |_, _| None);
+
+        // High enough that looking down shows what the top-down view did
+        let eye = camera.eye();
+        let height = 100. / (FOV / 2.).to_radians().tan();
+        assert_eq!((eye.x, eye.z), (10., -20.));
+        assert!((eye.y - height).abs() < 1e-3, "{:?}", eye);
+
+        // And over a mesh, as high above it as before
+        let mut camera = Camera::new(Point3::new(10., 50., -20.));
+        camera.zoom = 100.;
+        camera.set_mode(Mode::FreeFly, |_, _| Some(TOP_DOWN_HEIGHT - 30.));
+        assert!((camera.eye().y - (30. + height)).abs() < 1e-2);
+    }
+}
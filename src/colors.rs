@@ -0,0 +1,154 @@
+//! Colour modes for the path overlays
+
+use std::str::FromStr;
+
+/// What path lines are coloured by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorBy {
+    /// How far along its recording a sample is
+    Time,
+
+    /// Horizontal speed
+    Speed,
+
+    /// Horizontal distance to the target, brightest closest
+    Distance,
+
+    /// A distinct solid colour per path file
+    File,
+}
+
+/// All colour modes, in the order they are cycled through
+pub const COLOR_BYS: [ColorBy; 4] =
+    [ColorBy::Time, ColorBy::Speed, ColorBy::Distance, ColorBy::File];
+
+impl ColorBy {
+    /// Name of the mode, as accepted by `from_str`
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorBy::Time     => "time",
+            ColorBy::Speed    => "speed",
+            ColorBy::Distance => "distance",
+            ColorBy::File     => "file",
+        }
+    }
+
+    /// Value of the mode in the line shader's `color_by` uniform
+    pub fn uniform(self) -> i32 {
+        COLOR_BYS.iter().position(|&x| x == self).unwrap() as i32
+    }
+
+    /// Get the mode after this one
+    pub fn next(self) -> Self {
+        COLOR_BYS[(self.uniform() as usize + 1) % COLOR_BYS.len()]
+    }
+}
+
+impl FromStr for ColorBy {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        COLOR_BYS.iter().copied().find(|x| x.name() == name)
+            .ok_or_else(|| format!("Unknown colour mode {:?}", name))
+    }
+}
+
+/// How values from 0 to 1 are turned into colours
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Colormap {
+    /// Green intensity, as paths were always drawn
+    Green,
+
+    /// The perceptually uniform viridis colormap
+    #[default]
+    Viridis,
+}
+
+/// All colormaps, in the order they are cycled through
+pub const COLORMAPS: [Colormap; 2] = [Colormap::Green, Colormap::Viridis];
+
+impl Colormap {
+    /// Name of the colormap, as accepted by `from_str`
+    pub fn name(self) -> &'static str {
+        match self {
+            Colormap::Green   => "green",
+            Colormap::Viridis => "viridis",
+        }
+    }
+
+    /// Value of the colormap in the line shader's `colormap` uniform
+    pub fn uniform(self) -> i32 {
+        COLORMAPS.iter().position(|&x| x == self).unwrap() as i32
+    }
+
+    /// Get the colormap after this one
+    pub fn next(self) -> Self {
+        COLORMAPS[(self.uniform() as usize + 1) % COLORMAPS.len()]
+    }
+
+    /// Get the colour for `value` from 0 to 1, matching the line shader
+    pub fn color(self, value: f32) -> [f32; 3] {
+        let value = value.clamp(0., 1.);
+        match self {
+            Colormap::Green => [0., value, 0.],
+            Colormap::Viridis => {
+                let mut ret = [0.; 3];
+                for (channel, ret) in ret.iter_mut().enumerate() {
+                    *ret = VIRIDIS.iter().rev()
+                        .fold(0., |acc, x| acc * value + x[channel])
+                        .clamp(0., 1.);
+                }
+                ret
+            }
+        }
+    }
+}
+
+impl FromStr for Colormap {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        COLORMAPS.iter().copied().find(|x| x.name() == name)
+            .ok_or_else(|| format!("Unknown colormap {:?}", name))
+    }
+}
+
+/// Coefficients of a degree 6 polynomial fit of viridis, lowest first. These
+/// are passed to the line shader as the `viridis` uniform.
+pub const VIRIDIS: [[f32; 3]; 7] = [
+    [ 0.277_727_33,  0.005_407_344,  0.334_099_8 ],
+    [ 0.105_093_04,  1.404_613_5,    1.384_590_2 ],
+    [-0.330_861_83,  0.214_847_56,   0.095_095_16],
+    [-4.634_230_5,  -5.799_101,    -19.332_441   ],
+    [ 6.228_27,     14.179_933,     56.690_55    ],
+    [ 4.776_385,   -13.745_145,    -65.353_03    ],
+    [-5.435_456,     4.645_852_6,   26.312_435   ],
+];
+
+/// Colours given to path files in order, from the Tableau 10 palette so
+/// neighbours are easy to tell apart
+const PALETTE: [[f32; 3]; 10] = [
+    [0.122, 0.467, 0.706],
+    [1.000, 0.498, 0.055],
+    [0.173, 0.627, 0.173],
+    [0.839, 0.153, 0.157],
+    [0.580, 0.404, 0.741],
+    [0.549, 0.337, 0.294],
+    [0.890, 0.467, 0.761],
+    [0.498, 0.498, 0.498],
+    [0.737, 0.741, 0.133],
+    [0.090, 0.745, 0.812],
+];
+
+/// Get the colour of the path file at `index`
+pub fn file_color(index: usize) -> [f32; 3] {
+    PALETTE[index % PALETTE.len()]
+}
+
+/// Get a swatch of `color` for a terminal which supports 24-bit colour,
+/// followed by its hex code
+pub fn swatch(color: [f32; 3]) -> String {
+    let [r, g, b] = color.map(|x| (x.clamp(0., 1.) * 255.).round() as u8);
+    format!("\x1b[48;2;{};{};{}m    \x1b[0m #{:02x}{:02x}{:02x}",
+            r, g, b, r, g, b)
+}
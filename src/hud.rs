@@ -0,0 +1,85 @@
+//! Text overlay in the corner of the view, drawn with the bundled bitmap font
+//! by the legend program
+
+use crate::PathVertex;
+use crate::font;
+
+/// Screen pixels per font pixel
+const SCALE: f32 = 2.;
+
+/// Font pixels between characters and between lines
+const CHAR_GAP: usize = 1;
+const LINE_GAP: usize = 3;
+
+/// Space between the text and the edges of its background, and between the
+/// background and the edges of the view, in screen pixels
+const PADDING: f32 = 6.;
+const MARGIN:  f32 = 6.;
+
+/// Colour of the text
+const TEXT_COLOR: [f32; 3] = [1., 1., 1.];
+
+/// Colour and opacity of the background behind the text
+const BACKGROUND_COLOR: [f32; 3] = [0., 0., 0.];
+const BACKGROUND_ALPHA: f32 = 0.6;
+
+/// Add a solid rectangle at `x`, `y` pixels from the top left to `ret`
+fn quad(ret: &mut Vec<PathVertex>, x: f32, y: f32, w: f32, h: f32,
+        color: [f32; 3], alpha: f32) {
+    let vertex = |x: f32, y: f32| PathVertex {
+        position: [x, y, 0.],
+        values:   [0.; 3],
+        color,
+        solid:    1.,
+        alpha,
+    };
+    ret.extend_from_slice(&[
+        vertex(x,     y),
+        vertex(x,     y + h),
+        vertex(x + w, y + h),
+        vertex(x,     y),
+        vertex(x + w, y + h),
+        vertex(x + w, y),
+    ]);
+}
+
+/// Get the triangles drawing `lines` of text over a background in the top
+/// right corner of a view `width` pixels wide
+pub fn vertices(lines: &[String], width: f32) -> Vec<PathVertex> {
+    let advance = (font::WIDTH + CHAR_GAP) as f32 * SCALE;
+    let line_height = (font::HEIGHT + LINE_GAP) as f32 * SCALE;
+    let columns = lines.iter().map(|x| x.chars().count()).max().unwrap_or(0);
+    if columns == 0 {
+        return Vec::new();
+    }
+
+    // Leave off the gaps after the last character and line
+    let text_width  = columns as f32 * advance - CHAR_GAP as f32 * SCALE;
+    let text_height = lines.len() as f32 * line_height -
+        LINE_GAP as f32 * SCALE;
+    let left = width - MARGIN - PADDING - text_width;
+    let top  = MARGIN + PADDING;
+
+    let mut ret = Vec::new();
+    quad(&mut ret, left - PADDING, top - PADDING,
+         text_width + PADDING * 2., text_height + PADDING * 2.,
+         BACKGROUND_COLOR, BACKGROUND_ALPHA);
+
+    for (row, line) in lines.iter().enumerate() {
+        for (column, chr) in line.chars().enumerate() {
+            let glyph = font::glyph(chr);
+            let x = left + column as f32 * advance;
+            let y = top + row as f32 * line_height;
+            for gy in 0..font::HEIGHT {
+                for gx in 0..font::WIDTH {
+                    if font::pixel(&glyph, gx, gy) {
+                        quad(&mut ret, x + gx as f32 * SCALE,
+                             y + gy as f32 * SCALE, SCALE, SCALE,
+                             TEXT_COLOR, 1.);
+                    }
+                }
+            }
+        }
+    }
+    ret
+}
@@ -1,24 +1,100 @@
-use std::io;
-use std::fs::File;
-use std::io::{Read, BufReader};
+//! A simple viewer for falkvbo meshes, colouring triangles by their slope
+
+#![allow(clippy::print_with_newline)]
+
 use std::ffi::CString;
-use std::cell::Cell;
 use std::path::Path;
+use std::cell::Cell;
 use std::time::Instant;
 
 use gl::types::*;
 
-use cgmath::{Matrix4, Point3, Vector3, Deg, perspective};
+use cgmath::{Point3, Vector3, InnerSpace, EuclideanSpace};
 
 use sdl2::mouse::MouseButton;
 use sdl2::event::{Event, WindowEvent};
 use sdl2::video::SwapInterval;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Scancode};
+
+use parse_ealogpos::{Positions, SegmentOptions, Target, Approach};
+
+
+mod args;
+mod colors;
+mod playback;
+mod camera;
+mod pick;
+mod slopes;
+mod font;
+mod hud;
+
+use colors::{ColorBy, Colormap};
+use playback::{Playback, Recording, SCRUB_SECONDS};
+use camera::{Camera, Mode};
+use pick::{Bvh, Pick};
+use slopes::{Preset, Slopes};
+
+/// Height above the recorded positions paths are drawn at, so they sit on
+/// top of the ground rather than in it
+const PATH_HEIGHT: f32 = 0.5;
+
+/// Colour of highlighted lines, eg. jumps to the target
+const HIGHLIGHT_COLOR: [f32; 3] = [1., 1., 1.];
+
+/// Colour of the playback marker
+const MARKER_COLOR: [f32; 3] = [1., 1., 0.];
+
+/// Colour of the outline of the picked triangle
+const PICK_COLOR: [f32; 3] = [1., 0., 1.];
+
+/// Length of the line showing the picked triangle's normal, in yards
+const PICK_NORMAL: f32 = 2.;
+
+/// Height of the playback marker and length of the line showing its facing,
+/// in yards
+const MARKER_HEIGHT: f64 = 3.;
+const MARKER_FACING: f64 = 3.;
 
-use parse_ealogpos::Positions;
+/// Camera speed in yards per second to start with, the mouse wheel changes
+/// it
+const MOVE_SPEED: f32 = 50.;
+
+/// Factors applied to the camera speed while sprinting and crawling
+const SPRINT_FACTOR: f32 = 4.;
+const CRAWL_FACTOR:  f32 = 0.25;
+
+/// Distance the camera stays from the playback marker when following it
+const FOLLOW_DISTANCE: f32 = 30.;
+
+/// Size of the legend swatches and the gap around them, in pixels
+const LEGEND_SWATCH: f32 = 16.;
+const LEGEND_GAP:    f32 = 6.;
+
+/// Length of the legend's colour ramp, in pixels
+const LEGEND_RAMP: f32 = 128.;
+
+/// A vertex of the path overlays, laid out as the line shaders expect
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct PathVertex {
+    /// Position in GL coordinates
+    position: [f32; 3],
+
+    /// Values which can be coloured by, in `ColorBy` order, each from 0 to 1
+    values: [f32; 3],
+
+    /// Colour of the path file
+    color: [f32; 3],
+
+    /// 1 to always draw in `color`, for highlights and solid paths
+    solid: f32,
+
+    /// Opacity, from 0 to 1
+    alpha: f32,
+}
 
 // Vertex shader
-static VS_SRC: &'static str = "
+static VS_SRC: &str = "
 #version 150
 in vec3 position;
 uniform mat4 transform_matrix;
@@ -35,30 +111,90 @@ void main() {
 }";
 
 // Line vertex shader
-static LINE_VS_SRC: &'static str = "
+static LINE_VS_SRC: &str = "
 #version 150
-in vec4 position;
+in vec3 position;
+in vec3 values;
+in vec3 color;
+in float solid;
+in float alpha;
 uniform mat4 transform_matrix;
-out vec4 vs_pos;
+out vec3 vs_values;
+out vec3 vs_color;
+out float vs_solid;
+out float vs_alpha;
 
 void main() {
-    vs_pos = position;
+    vs_values = values;
+    vs_color = color;
+    vs_solid = solid;
+    vs_alpha = alpha;
     gl_Position = transform_matrix * vec4(position.x, position.y, position.z, 1.0);
 }";
 
+// Legend vertex shader, positions are in pixels from the top left
+static LEGEND_VS_SRC: &str = "
+#version 150
+in vec3 position;
+in vec3 values;
+in vec3 color;
+in float solid;
+in float alpha;
+uniform vec2 pixel_scale;
+out vec3 vs_values;
+out vec3 vs_color;
+out float vs_solid;
+out float vs_alpha;
+
+void main() {
+    vs_values = values;
+    vs_color = color;
+    vs_solid = solid;
+    vs_alpha = alpha;
+    gl_Position = vec4(-1.0 + position.x * pixel_scale.x,
+                       1.0 - position.y * pixel_scale.y, 0.0, 1.0);
+}";
+
 // Line fragment shader
-static LINE_FS_SRC: &'static str = "
+static LINE_FS_SRC: &str = "
 #version 150
+in vec3 vs_values;
+in vec3 vs_color;
+in float vs_solid;
+in float vs_alpha;
 out vec4 out_color;
-in vec4 vs_pos;
+
+// What to colour by, a `ColorBy`
+uniform int color_by;
+
+// How to turn values into colours, a `Colormap`
+uniform int colormap;
+
+// Polynomial coefficients of the viridis colormap, lowest first
+uniform vec3 viridis[7];
 
 void main() {
-    out_color = vec4(0.0, vs_pos.w, 0.0, 1.0);
+    // Solid lines and per file colouring use the vertex colour
+    if(vs_solid > 0.5 || color_by == 3) {
+        out_color = vec4(vs_color, vs_alpha);
+        return;
+    }
+
+    float value = clamp(vs_values[color_by], 0.0, 1.0);
+    if(colormap == 0) {
+        out_color = vec4(0.0, value, 0.0, vs_alpha);
+    } else {
+        vec3 color = viridis[6];
+        for(int ii = 5; ii >= 0; ii--) {
+            color = color * value + viridis[ii];
+        }
+        out_color = vec4(clamp(color, 0.0, 1.0), vs_alpha);
+    }
 }
 ";
 
 // Triangle fragment shader
-static FS_SRC: &'static str = "
+static FS_SRC: &str = "
 #version 150
 
 in vec4 geom_color;
@@ -80,7 +216,7 @@ void main() {
 }";
 
 // Geometry shader
-static GS_SRC: &'static str = "
+static GS_SRC: &str = "
 #version 150
 
 layout (triangles) in;
@@ -93,6 +229,20 @@ in VS_OUT {
 out vec4 geom_color;
 out vec2 geom_uv;
 
+// Slopes in degrees up to which triangles are climbable and unclimbable,
+// steeper ones are inverted
+uniform float climbable_slope;
+uniform float inverted_slope;
+
+// Colours at the shallow and steep ends of each class
+uniform vec3 ramps[6];
+
+// Colormap to colour by slope with rather than by class, a `Colormap`, or -1
+uniform int slope_colormap;
+
+// Polynomial coefficients of the viridis colormap, lowest first
+uniform vec3 viridis[7];
+
 vec3 GetNormal()
 {
    vec3 a = vec3(gs_in[0].orig_position) - vec3(gs_in[1].orig_position);
@@ -100,6 +250,13 @@ vec3 GetNormal()
    return normalize(cross(a, b));
 }
 
+// Get the colour along ramp `idx` for a slope from `start` to `end`
+vec3 Ramp(int idx, float start, float end, float slope)
+{
+   float value = clamp((slope - start) / max(end - start, 0.0001), 0.0, 1.0);
+   return mix(ramps[idx * 2], ramps[idx * 2 + 1], value);
+}
+
 void main() {
     vec3 normal = GetNormal();
 
@@ -109,26 +266,25 @@ void main() {
     // 180 degrees = Flat surface, but upside-down, like looking at a ceiling
     float slope = degrees(acos(max(-1.0, min(1.0, -normal.y))));
 
-    // Color is 0.1 0.1 0.1 for steepest slope
-    // Color is 0.9 0.9 0.9 for flattest slope
-    vec4 color;
-    
-    if(slope <= 50.) {
+    vec3 ramp_color;
+    if(slope_colormap == 0) {
+        ramp_color = vec3(0.0, slope / 180.0, 0.0);
+    } else if(slope_colormap == 1) {
+        ramp_color = viridis[6];
+        for(int ii = 5; ii >= 0; ii--) {
+            ramp_color = ramp_color * (slope / 180.0) + viridis[ii];
+        }
+    } else if(slope <= climbable_slope) {
         // Climbable triangle
-        // flat plane = 0.9, 0.9, 0.9
-        // 50 degree  = 0.1, 0.1, 0.1
-        color = vec4(0.1, 0.1, 0.1, 1.0) + (50 - slope) / 62.5;
-    } else if(slope <= 90.) {
+        ramp_color = Ramp(0, 0.0, climbable_slope, slope);
+    } else if(slope <= inverted_slope) {
         // Unclimbable triangle
-        //  50 degree = 0.9, 0.9, 0.9
-        // 180 degree = 0.1, 0.1, 0.1
-        color = vec4(0.0, 0.0, 0.3, 1.0) + (40 - (slope - 50)) / 50;
+        ramp_color = Ramp(1, climbable_slope, inverted_slope, slope);
     } else {
         // Inverted triangle
-        //  90 degree = 0.9, 0.9, 0.9
-        // 180 degree = 0.1, 0.1, 0.1
-        color = vec4(0.3, 0.0, 0.0, 1.0) + (90 - (slope - 90)) / 112.5;
+        ramp_color = Ramp(2, inverted_slope, 180.0, slope);
     }
+    vec4 color = vec4(clamp(ramp_color, 0.0, 1.0), 1.0);
 
     //color *= 1 + (abs(normal.z) / 10.0);
 
@@ -168,8 +324,8 @@ fn compile_shader(src: &str, ty: GLenum) -> GLuint {
         if status != (gl::TRUE as GLint) {
             let mut len = 0;
             gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
-            let mut buf = Vec::with_capacity(len as usize);
-            buf.set_len((len as usize) - 1); // subtract 1 to skip the trailing null character
+            // subtract 1 to skip the trailing null character
+            let mut buf = vec![0u8; (len as usize).saturating_sub(1)];
             gl::GetShaderInfoLog(
                 shader,
                 len,
@@ -179,7 +335,6 @@ fn compile_shader(src: &str, ty: GLenum) -> GLuint {
             panic!(
                 "{}",
                 std::str::from_utf8(&buf)
-                    .ok()
                     .expect("ShaderInfoLog not valid utf8")
             );
         }
@@ -204,8 +359,8 @@ fn link_program(vs: GLuint, fs: GLuint, gs: Option<GLuint>) -> GLuint {
         if status != (gl::TRUE as GLint) {
             let mut len: GLint = 0;
             gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
-            let mut buf = Vec::with_capacity(len as usize);
-            buf.set_len((len as usize) - 1); // subtract 1 to skip the trailing null character
+            // subtract 1 to skip the trailing null character
+            let mut buf = vec![0u8; (len as usize).saturating_sub(1)];
             gl::GetProgramInfoLog(
                 program,
                 len,
@@ -215,7 +370,6 @@ fn link_program(vs: GLuint, fs: GLuint, gs: Option<GLuint>) -> GLuint {
             panic!(
                 "{}",
                 std::str::from_utf8(&buf)
-                    .ok()
                     .expect("ProgramInfoLog not valid utf8")
             );
         }
@@ -223,192 +377,443 @@ fn link_program(vs: GLuint, fs: GLuint, gs: Option<GLuint>) -> GLuint {
     }
 }
 
-/// Load a falkvbo file containing the unique verticies and a list of triangle
-/// indicies
-pub fn load_falkvbo<P: AsRef<Path>>(path: P)
-        -> io::Result<(Vec<(f32, f32, f32)>, Vec<(u32, u32, u32)>)> {
-    // Open the file
-    let mut fd = BufReader::new(File::open(path)?);
-
-    // Create the vertex and triangle buffers
-    let mut verticies = Vec::new();
-    let mut triangles = Vec::new();
-
-    // Get the number of verticies
-    let mut num_verticies = [0u8; 8];
-    fd.read_exact(&mut num_verticies)?;
-    let num_verticies = u64::from_le_bytes(num_verticies);
-
-    // Load the vertex data
-    for _ in 0..num_verticies {
-        let mut x = [0u8; 4];
-        fd.read_exact(&mut x)?;
-        let x = f32::from_le_bytes(x);
-        
-        let mut y = [0u8; 4];
-        fd.read_exact(&mut y)?;
-        let y = f32::from_le_bytes(y);
-        
-        let mut z = [0u8; 4];
-        fd.read_exact(&mut z)?;
-        let z = f32::from_le_bytes(z);
+/// Get the vertices for the playback marker and the trail fading out behind
+/// it
+fn playback_vertices(playback: &Playback) -> Vec<PathVertex> {
+    let mut ret = Vec::new();
+    for pair in playback.trail().windows(2) {
+        for &(pos, fade) in pair {
+            ret.push(PathVertex {
+                alpha: fade,
+                ..highlight_vertex([pos.x, pos.y, pos.z])
+            });
+        }
+    }
 
-        // Save the vertex data
-        verticies.push((x, y, z));
+    // A post at the marker with a line off the top in the way it's facing
+    if let Some(marker) = playback.marker() {
+        let angle  = marker.angle.to_radians();
+        let base   = [marker.x, marker.y, marker.z];
+        let top    = [marker.x, marker.y, marker.z + MARKER_HEIGHT];
+        let facing = [marker.x + angle.cos() * MARKER_FACING,
+                      marker.y + angle.sin() * MARKER_FACING,
+                      marker.z + MARKER_HEIGHT];
+        for &point in &[base, top, top, facing] {
+            ret.push(PathVertex {
+                color: MARKER_COLOR,
+                ..highlight_vertex(point)
+            });
+        }
     }
-    
-    // Get the number of triangles
-    let mut num_triangles = [0u8; 8];
-    fd.read_exact(&mut num_triangles)?;
-    let num_triangles = u64::from_le_bytes(num_triangles);
-
-    // Load the triangle data
-    for _ in 0..num_triangles {
-        let mut a = [0u8; 4];
-        fd.read_exact(&mut a)?;
-        let a = u32::from_le_bytes(a);
-        
-        let mut b = [0u8; 4];
-        fd.read_exact(&mut b)?;
-        let b = u32::from_le_bytes(b);
-        
-        let mut c = [0u8; 4];
-        fd.read_exact(&mut c)?;
-        let c = u32::from_le_bytes(c);
+    ret
+}
+
+/// Get the lines outlining the picked triangle, with its normal sticking out
+/// of the middle
+fn pick_vertices(pick: &Pick) -> Vec<PathVertex> {
+    let vertex = |point: Point3<f32>| PathVertex {
+        position: [point.x, point.y, point.z],
+        color:    PICK_COLOR,
+        ..highlight_vertex([0.; 3])
+    };
+    let [a, b, c] = pick.corners;
+    let middle = Point3::centroid(&pick.corners);
+    [a, b, b, c, c, a, middle, middle + pick.normal * PICK_NORMAL]
+        .iter().map(|&x| vertex(x)).collect()
+}
 
-        // Save the triangle data
-        triangles.push((a, b, c));
+/// Get the lines of the HUD
+fn hud_lines(camera: &Camera, move_speed: f32, fps: f64,
+             counts: (usize, usize), picked: Option<&Pick>,
+             preset: &Preset, playback: &Playback) -> Vec<String> {
+    let eye = camera.eye();
+    let mut ret = vec![
+        format!("FPS {:.1}", fps),
+        format!("{} triangles, {} verticies", counts.0, counts.1),
+        format!("GL  {:.2} {:.2} {:.2}", eye.x, eye.y, eye.z),
+        format!("WoW {:.2} {:.2} {:.2}", eye.z, eye.x, eye.y),
+        format!("{} camera, heading {:.1}\u{b0}", camera.mode.name(),
+                camera.heading()),
+        format!("Speed {:.1} yd/s", move_speed),
+        format!("Slopes {}: climbable {:.2}\u{b0}, inverted {:.2}\u{b0}",
+                preset.name, preset.climbable, preset.inverted),
+        match picked {
+            Some(pick) => format!("Picked {}: {:.2}\u{b0}, {}",
+                                  pick.triangle, pick.slope,
+                                  preset.class(pick.slope).name()),
+            None => "Right click to pick a triangle".into(),
+        },
+    ];
+    if playback.active {
+        ret.push(playback.status());
     }
+    ret
+}
 
-    // Return the loaded data!
-    Ok((verticies, triangles))
+/// Print the picked triangle, classified by `preset`
+fn print_pick(pick: &Pick, preset: &Preset) {
+    print!("Picked triangle {} at {:.4} {:.4} {:.4}\n", pick.triangle,
+           pick.point.z, pick.point.x, pick.point.y);
+    for corner in &pick.corners {
+        print!("    vertex {:.4} {:.4} {:.4}\n", corner.z, corner.x, corner.y);
+    }
+    print!("    normal {:.4} {:.4} {:.4}\n",
+           pick.normal.z, pick.normal.x, pick.normal.y);
+    print!("    slope {:.2} degrees, {}\n", pick.slope,
+           preset.class(pick.slope).name());
+}
+
+/// Get a highlight vertex for a path line at WoW coordinates `pos`
+fn highlight_vertex(pos: [f64; 3]) -> PathVertex {
+    PathVertex {
+        position: [pos[1] as f32, pos[2] as f32 + PATH_HEIGHT, pos[0] as f32],
+        values:   [1.; 3],
+        color:    HIGHLIGHT_COLOR,
+        solid:    1.,
+        alpha:    1.,
+    }
+}
+
+/// Get the vertices for the legend, a swatch for each path file followed by
+/// the colour ramp. Returns the vertices and how many are swatches.
+fn legend_vertices(colors: &[[f32; 3]]) -> (Vec<PathVertex>, usize) {
+    let mut ret = Vec::new();
+    let mut quad = |x: f32, y: f32, w: f32, h: f32, bottom: f32, top: f32,
+                    color: [f32; 3], solid: f32| {
+        let vertex = |x: f32, y: f32, value: f32| PathVertex {
+            position: [x, y, 0.],
+            values:   [value; 3],
+            color,
+            solid,
+            alpha: 1.,
+        };
+        ret.extend_from_slice(&[
+            vertex(x,     y,     top),
+            vertex(x,     y + h, bottom),
+            vertex(x + w, y + h, bottom),
+            vertex(x,     y,     top),
+            vertex(x + w, y + h, bottom),
+            vertex(x + w, y,     top),
+        ]);
+    };
+
+    let mut y = LEGEND_GAP;
+    for &color in colors {
+        quad(LEGEND_GAP, y, LEGEND_SWATCH, LEGEND_SWATCH, 0., 0., color, 1.);
+        y += LEGEND_SWATCH + LEGEND_GAP;
+    }
+    let swatches = colors.len() * 6;
+
+    // The ramp has the highest values at the top
+    quad(LEGEND_GAP, y, LEGEND_SWATCH, LEGEND_RAMP, 0., 1., [0.; 3], 0.);
+    (ret, swatches)
+}
+
+/// Print which colour each path is drawn in, and what the colours of the
+/// paths which are coloured by value mean
+fn print_legend(paths: &[args::PathArg], colors: &[[f32; 3]],
+                color_by: ColorBy, colormap: Colormap,
+                ranges: &[(f64, f64, &str); 3]) {
+    print!("Path colours:\n");
+    for (path, &color) in paths.iter().zip(colors) {
+        print!("    {} {}{}\n", colors::swatch(color), path.filename.display(),
+               if path.color.is_some() { " (solid)" } else { "" });
+    }
+
+    if color_by != ColorBy::File {
+        let (low, high, unit) = ranges[color_by.uniform() as usize];
+        print!("Colouring by {} with {}: {} {:.1} {} to {} {:.1} {}\n",
+               color_by.name(), colormap.name(),
+               colors::swatch(colormap.color(0.)), low, unit,
+               colors::swatch(colormap.color(1.)), high, unit);
+    } else {
+        print!("Colouring by file\n");
+    }
+}
+
+/// Point the attributes of the bound VAO at `PathVertex`es in the bound
+/// buffer, for a program using the line fragment shader
+unsafe fn path_attributes(program: GLuint) {
+    let stride = core::mem::size_of::<PathVertex>() as GLint;
+    let attributes = [("position", 3, 0), ("values", 3, 3), ("color", 3, 6),
+                      ("solid", 1, 9), ("alpha", 1, 10)];
+    for &(name, size, offset) in &attributes {
+        let name = CString::new(name).unwrap();
+        let attr = gl::GetAttribLocation(program, name.as_ptr());
+        gl::EnableVertexAttribArray(attr as GLuint);
+        gl::VertexAttribPointer(
+            attr as GLuint,
+            size,
+            gl::FLOAT,
+            gl::FALSE as GLboolean,
+            stride,
+            (offset * core::mem::size_of::<f32>()) as *const _,
+        );
+    }
+}
+
+/// Print where the path in `filename` comes closest to the target
+fn print_approach(filename: &Path, approach: &Approach) {
+    let pos = approach.position;
+    print!("Closest approach to the target in {}: sample {} ({:.3} s)\n",
+           filename.display(), approach.index, pos.time);
+    print!("    jump from {:.4} {:.4} {:.4} to {:.4} {:.4} {:.4}\n",
+           pos.x, pos.y, pos.z,
+           approach.target[0], approach.target[1], approach.target[2]);
+    print!("    {:.4} yards away, {:.4} yards up\n",
+           approach.distance, approach.height);
+    print!("    {:.4} absolute heading\n", approach.heading);
+    if let (Some(platform), Some(relative)) =
+            (approach.platform_heading, approach.relative_heading) {
+        print!("    {:.4} relative to the platform heading {:.4}\n",
+               relative, platform);
+    }
+}
+
+/// Print the information from a falkvbo header
+fn print_header(header: &falkvbo::Header) {
+    print!("falkvbo version {} | map {} {:?} | bbox {:?} - {:?}\n",
+           header.version,
+           header.map_id.map(|x| x.to_string())
+               .unwrap_or_else(|| "unknown".into()),
+           header.map_name, header.bbox.0, header.bbox.1);
+    for (key, val) in &header.metadata {
+        print!("    {}: {}\n", key, val);
+    }
 }
 
 pub fn main() {
     // Get the arguments
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        print!("Usage: {} <falkvbo file>\n", args[0]);
-        return;
+    let mut args = std::env::args();
+    let prog = args.next().unwrap_or_else(|| "simple_slope_viewer".into());
+    let args = match args::parse(args) {
+        Ok(args::Command::Run(args)) => *args,
+        Ok(args::Command::Help) => {
+            print!("{}", args::usage(&prog));
+            return;
+        }
+        Err(err) => {
+            print!("{}\n\n{}", err, args::usage(&prog));
+            std::process::exit(1);
+        }
+    };
+
+    // Only validate the mesh if requested
+    if args.check {
+        match falkvbo::check(&args.mesh) {
+            Ok((header, report)) => {
+                print_header(&header);
+                print!("{}\n", report);
+                if report.has_errors() {
+                    print!("{}: INVALID\n", args.mesh.display());
+                    std::process::exit(1);
+                }
+                print!("{}: OK\n", args.mesh.display());
+                return;
+            }
+            Err(err) => {
+                print!("{}: {}\n", args.mesh.display(), err);
+                std::process::exit(1);
+            }
+        }
     }
-    
+
+    // Load the slope presets before the mesh, so mistakes show up quickly
+    let mut slopes = Slopes::new(args.slope_file.clone()).and_then(|mut x| {
+        if let Some(name) = &args.slope_preset {
+            x.select(name)?;
+        }
+        Ok(x)
+    }).unwrap_or_else(|err| {
+        print!("{}\n", err);
+        std::process::exit(1);
+    });
+    print!("{}\n", slopes.preset().describe());
+
     let mut head_pos: Point3<f32> = Point3::new(0., 1000., 0.);
 
-    print!("Loading LUA data...\n");
-
-    let mut path_data: Vec<(f32, f32, f32, f32)> = Vec::new();
-            
-    // Tgt triangle
-    // 186.73837, 95.57352, 1843.6765
-    // 176.75186, 96.93022, 1838.2499
-    //let land_target_x = 1838.2499;
-    //let land_target_y = 176.75186;
-    let land_target_x = (1843.6765 + 1838.2499) / 2.;
-    let land_target_y = (186.73837 + 176.75186) / 2.;
-
-    let positions = Positions::from_lua("gg_uc_zep.lua")
-        .expect("Failed to load LUA path");
-    let mut closest = std::f64::MAX;
-    let mut idx = Cell::new(1);
-    positions.positions.windows(2)
-        .filter(|poss| {
-            idx.set(idx.get() + 1);
-            let y_delta = poss[1].y - poss[0].y;
-            let x_delta = poss[1].x - poss[0].x;
-            let delta = ((y_delta * y_delta) + (x_delta * x_delta)).sqrt();
-            poss[0].map_id == 0 && poss[0].map_id == poss[1].map_id &&
-                delta < 10.
-        })
-        .for_each(|poss| {
-            let x_delta = land_target_x - poss[0].x;
-            let y_delta = land_target_y - poss[0].y;
-            let delta_target =
-                ((y_delta * y_delta) + (x_delta * x_delta)).sqrt();
-
-            let delta_pct = delta_target / 121.75096014;
-
-            let color = 1.0 - ((delta_pct - 1.0) * 500.).min(1.0);
-            let color = color as f32;
-
-
-            if delta_target < closest {
-                closest = delta_target;
-                //print!("{}\n", closest);
-            }
+    let mut path_data: Vec<PathVertex> = Vec::new();
+
+    // Outline the target and start above it
+    if let Some(target) = &args.target {
+        let points = match target {
+            Target::Point(point) => vec![*point],
+            Target::Triangle(tri) => tri.to_vec(),
+        };
+        for (ii, point) in points.iter().enumerate() {
+            let next = points[(ii + 1) % points.len()];
+            path_data.push(highlight_vertex(*point));
+            path_data.push(highlight_vertex(next));
+        }
+
+        let count = points.len() as f64;
+        head_pos.x = (points.iter().map(|x| x[1]).sum::<f64>() / count) as f32;
+        head_pos.z = (points.iter().map(|x| x[0]).sum::<f64>() / count) as f32;
+    }
 
-            if delta_target == 121.75096014778174 {
-                print!("WOO CLOSEST\n");
-
-                let delta_x = (poss[0].x - land_target_x).abs();
-                let delta_y = (poss[0].y - land_target_y).abs();
-
-                let jump_angle = (delta_y / delta_x).atan().to_degrees();
-                print!("X {} Y {} jump from\n", poss[0].x, poss[0].y);
-                print!("{} absolute angle\n", 180. - jump_angle);
-                print!("{} stationary boat angle\n",
-                       (180. - (poss[0].angle - 82.691023903837)) - jump_angle);
-            
-                if true {
-                    head_pos.x = land_target_y as f32;
-                    head_pos.z = land_target_x as f32;
-                } else {
-                    head_pos.x = poss[0].y as f32;
-                    head_pos.z = poss[0].x as f32;
+    // Colour of each path file, and the highest speed and distance from the
+    // target to scale those values by
+    let mut file_colors  = Vec::new();
+    let mut max_speed    = 0f64;
+    let mut max_distance = 0f64;
+    let mut recordings   = Vec::new();
+
+    for (path_idx, path) in args.paths.iter().enumerate() {
+        print!("Loading positions from {}...\n", path.filename.display());
+
+        let positions = match Positions::load(&path.filename,
+                                              args.strictness) {
+            Ok((positions, errors)) => {
+                for err in &errors {
+                    print!("Skipped bad record in {}: {}\n",
+                           path.filename.display(), err);
                 }
-                
-                path_data.push((poss[0].y as f32, 100.,
-                                poss[0].x as f32, 1.0));
-                path_data.push((land_target_y as f32, 100.,
-                                land_target_x as f32, 1.0));
+                positions
+            }
+            Err(err) => {
+                print!("Failed to load path {}: {}\n",
+                       path.filename.display(), err);
+                std::process::exit(1);
             }
+        };
+
+        // Split the recording where it isn't continuous, the breaks
+        // between segments aren't drawn
+        let options = SegmentOptions {
+            gap_threshold: args.gap_threshold
+                .unwrap_or(parse_ealogpos::DEFAULT_GAP_THRESHOLD),
+            ..SegmentOptions::default()
+        };
+        let segments = positions.segments(&options);
+        print!("{} segments in {}\n", segments.len(), path.filename.display());
+        for segment in &segments {
+            print!("    samples {:>6}-{:<6} map {:>3}: {:8.1} s {:9.1} yards \
+                    {:6.2} yards/s{}\n",
+                   segment.start, segment.start + segment.positions.len() - 1,
+                   segment.map_id(), segment.duration, segment.length,
+                   segment.average_speed(),
+                   segment.split.map(|x| format!(", then {:?}", x))
+                       .unwrap_or_default());
+        }
 
-            path_data.push((poss[0].y as f32, 100.,
-                            poss[0].x as f32, color));
-            path_data.push((poss[1].y as f32, 100.,
-                            poss[1].x as f32, color));
+        let segments: Vec<_> = segments.iter().filter(|segment| {
+            args.map_id.map(|x| segment.map_id() == x).unwrap_or(true)
+        }).collect();
+
+        // Find where the path comes closest to the target, and highlight the
+        // jump from there
+        let approach = args.target.and_then(|target| {
+            target.closest_approach(segments.iter().copied(), args.platform)
         });
-        
+        if let Some(approach) = &approach {
+            print_approach(&path.filename, approach);
+            let pos = approach.position;
+            path_data.push(highlight_vertex([pos.x, pos.y, pos.z]));
+            path_data.push(highlight_vertex(approach.target));
+        }
+
+        // Speeds and how far along the recording each sample is
+        let analysis = positions.analyze(&options);
+        let first = positions.positions.first().map(|x| x.time).unwrap_or(0.);
+        let last  = positions.positions.last().map(|x| x.time).unwrap_or(0.);
+        let duration = (last - first).max(f64::EPSILON);
+
+        // Paths with a solid colour are drawn in it whatever the mode
+        let (color, solid) = match path.color {
+            Some(intensity) => ([0., intensity, 0.], 1.),
+            None => (colors::file_color(path_idx), 0.),
+        };
+        file_colors.push(color);
+
+        for segment in segments {
+            let end = segment.start + segment.positions.len();
+            let vertices: Vec<PathVertex> = (segment.start..end).map(|ii| {
+                let pos = &positions.positions[ii];
+                let speed = analysis.samples[ii].speed;
+                let distance = args.target
+                    .map(|target| target.distance(pos.x, pos.y))
+                    .unwrap_or(0.);
+                if solid == 0. {
+                    max_speed    = max_speed.max(speed);
+                    max_distance = max_distance.max(distance);
+                }
+
+                PathVertex {
+                    position: [pos.y as f32, pos.z as f32 + PATH_HEIGHT,
+                               pos.x as f32],
+                    values: [((pos.time - first) / duration) as f32,
+                             speed as f32, distance as f32],
+                    color,
+                    solid,
+                    alpha: 1.,
+                }
+            }).collect();
+
+            for pair in vertices.windows(2) {
+                path_data.extend_from_slice(pair);
+            }
+        }
+
+        // Play back the samples on the map being viewed
+        let shown = Positions { positions: positions.positions.iter()
+            .filter(|x| args.map_id.map(|id| x.map_id == id).unwrap_or(true))
+            .copied().collect() };
+        recordings.extend(Recording::new(path.filename.display().to_string(),
+                                         shown, &options));
+    }
+
+    // Scale speeds and distances, near the target is brightest
+    for vertex in path_data.iter_mut().filter(|x| x.solid == 0.) {
+        vertex.values[1] /= max_speed.max(f64::EPSILON) as f32;
+        vertex.values[2] = 1. -
+            vertex.values[2] / max_distance.max(f64::EPSILON) as f32;
+    }
+
+    let mut playback = Playback::new(recordings);
+
+    let mut color_by = args.color_by.unwrap_or(if args.target.is_some() {
+        ColorBy::Distance
+    } else {
+        ColorBy::Time
+    });
+    let mut colormap = args.colormap;
+    let ranges = [
+        (0., 100., "% of the recording"),
+        (0., max_speed, "yards/s"),
+        (max_distance, 0., "yards from the target"),
+    ];
+    print_legend(&args.paths, &file_colors, color_by, colormap, &ranges);
+
     // A decrease in our X (WoW's Y) is west
     // An increase in our Z (WoW's X) is north
 
-    let positions = Positions::from_lua("jumpatt2_success.lua")
-        .expect("Failed to load LUA path");
-    positions.positions.windows(2)
-        .filter(|poss| {
-            idx.set(idx.get() + 1);
-            let y_delta = poss[1].y - poss[0].y;
-            let x_delta = poss[1].x - poss[0].x;
-            let delta = ((y_delta * y_delta) + (x_delta * x_delta)).sqrt();
-            poss[0].map_id == 0 && poss[0].map_id == poss[1].map_id &&
-                delta < 10.
-        })
-        .for_each(|poss| {
-            path_data.push((poss[0].y as f32, 100., poss[0].x as f32, 0.1));
-            path_data.push((poss[1].y as f32, 100., poss[1].x as f32, 0.1));
-        });
-    let positions = Positions::from_lua("jumpatt_success2.lua")
-        .expect("Failed to load LUA path");
-    positions.positions.windows(2)
-        .filter(|poss| {
-            idx.set(idx.get() + 1);
-            let y_delta = poss[1].y - poss[0].y;
-            let x_delta = poss[1].x - poss[0].x;
-            let delta = ((y_delta * y_delta) + (x_delta * x_delta)).sqrt();
-            poss[0].map_id == 0 && poss[0].map_id == poss[1].map_id &&
-                delta < 10.
-        })
-        .for_each(|poss| {
-            path_data.push((poss[0].y as f32, 100., poss[0].x as f32, 0.5));
-            path_data.push((poss[1].y as f32, 100., poss[1].x as f32, 0.5));
-        });
+    // An explicit start position overrides anything picked from the paths
+    if let Some((x, y, z)) = args.start_pos {
+        head_pos = Point3::new(y, z, x);
+    }
     
     print!("Loading falkvbo data...\n");
 
-    // Get the vertex data and indicies for the data in our object file
-    let (vertex_data, triangles) =
-        load_falkvbo(&args[1]).expect("Failed to load falkvbo data");
+    // Map in the vertex data and indicies for the data in our object file
+    let vbo = match falkvbo::map(&args.mesh) {
+        Ok(vbo) => vbo,
+        Err(err) => {
+            print!("Failed to load falkvbo data from {}: {}\n",
+                   args.mesh.display(), err);
+            std::process::exit(1);
+        }
+    };
 
     print!("Falkvbo data loaded!\n");
+    print_header(&vbo.header);
+
+    let vertex_data = vbo.vertices();
+    let triangles   = vbo.triangles();
+
+    // Build the hierarchy for picking triangles
+    let bvh_start = Instant::now();
+    let bvh = Bvh::new(vertex_data, triangles);
+    print!("Built picking BVH in {:.3} s\n", bvh_start.elapsed().as_secs_f64());
 
     // Create an SDL context
     let sdl_context = sdl2::init().unwrap();
@@ -457,11 +862,24 @@ pub fn main() {
     let line_fs = compile_shader(LINE_FS_SRC, gl::FRAGMENT_SHADER);
     let line_program = link_program(line_vs, line_fs, None);
 
+    // The legend shares the line fragment shader
+    let legend_vs = compile_shader(LEGEND_VS_SRC, gl::VERTEX_SHADER);
+    let legend_program = link_program(legend_vs, line_fs, None);
+    let (legend_data, legend_swatches) = legend_vertices(&file_colors);
+
     let mut vao = 0;
     let mut path_vao = 0;
+    let mut legend_vao = 0;
+    let mut playback_vao = 0;
+    let mut pick_vao = 0;
+    let mut hud_vao = 0;
     let mut vbo = 0;
     let mut ele_buffer = 0;
     let mut path_buffer = 0;
+    let mut legend_buffer = 0;
+    let mut playback_buffer = 0;
+    let mut pick_buffer = 0;
+    let mut hud_buffer = 0;
 
     unsafe {
         // Create Vertex Array Object
@@ -473,7 +891,7 @@ pub fn main() {
         gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
         gl::BufferData(
             gl::ARRAY_BUFFER,
-            core::mem::size_of_val(&vertex_data[..]) as isize,
+            core::mem::size_of_val(vertex_data) as isize,
             vertex_data.as_ptr() as *const _,
             gl::STATIC_DRAW,
         );
@@ -498,7 +916,7 @@ pub fn main() {
         gl::GenBuffers(1, &mut ele_buffer);
         gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ele_buffer);
         gl::BufferData(gl::ELEMENT_ARRAY_BUFFER,
-            core::mem::size_of_val(&triangles[..]) as isize,
+            core::mem::size_of_val(triangles) as isize,
             triangles.as_ptr() as *const _, gl::STATIC_DRAW);
         
         // Set up the path VAO
@@ -517,18 +935,53 @@ pub fn main() {
         gl::UseProgram(line_program);
         gl::BindFragDataLocation(line_program, 0,
                                  CString::new("out_color").unwrap().as_ptr());
-        let pos_attr = gl::GetAttribLocation(line_program,
-                                             CString::new("position")
-                                             .unwrap().as_ptr());
-        gl::EnableVertexAttribArray(pos_attr as GLuint);
-        gl::VertexAttribPointer(
-            pos_attr as GLuint,
-            4,
-            gl::FLOAT,
-            gl::FALSE as GLboolean,
-            0,
-            std::ptr::null(),
+        path_attributes(line_program);
+
+        // Set up the legend VAO
+        gl::GenVertexArrays(1, &mut legend_vao);
+        gl::BindVertexArray(legend_vao);
+        gl::GenBuffers(1, &mut legend_buffer);
+        gl::BindBuffer(gl::ARRAY_BUFFER, legend_buffer);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            core::mem::size_of_val(&legend_data[..]) as isize,
+            legend_data.as_ptr() as *const _,
+            gl::STATIC_DRAW,
         );
+        gl::UseProgram(legend_program);
+        gl::BindFragDataLocation(legend_program, 0,
+                                 CString::new("out_color").unwrap().as_ptr());
+        path_attributes(legend_program);
+
+        // Set up the playback VAO, its buffer is filled in as it plays
+        gl::GenVertexArrays(1, &mut playback_vao);
+        gl::BindVertexArray(playback_vao);
+        gl::GenBuffers(1, &mut playback_buffer);
+        gl::BindBuffer(gl::ARRAY_BUFFER, playback_buffer);
+        path_attributes(line_program);
+
+        // Set up the picked triangle VAO, filled in when something is picked
+        gl::GenVertexArrays(1, &mut pick_vao);
+        gl::BindVertexArray(pick_vao);
+        gl::GenBuffers(1, &mut pick_buffer);
+        gl::BindBuffer(gl::ARRAY_BUFFER, pick_buffer);
+        path_attributes(line_program);
+
+        // Set up the HUD VAO, filled in as the text changes
+        gl::GenVertexArrays(1, &mut hud_vao);
+        gl::BindVertexArray(hud_vao);
+        gl::GenBuffers(1, &mut hud_buffer);
+        gl::BindBuffer(gl::ARRAY_BUFFER, hud_buffer);
+        path_attributes(legend_program);
+
+        // All programs colour with viridis the same way
+        let viridis_name = CString::new("viridis").unwrap();
+        for &prog in &[program, line_program, legend_program] {
+            gl::UseProgram(prog);
+            gl::Uniform3fv(gl::GetUniformLocation(prog, viridis_name.as_ptr()),
+                           colors::VIRIDIS.len() as GLint,
+                           colors::VIRIDIS.as_ptr() as *const GLfloat);
+        }
 
         gl::Enable(gl::CULL_FACE);
         gl::Enable(gl::DEPTH_TEST);
@@ -540,9 +993,8 @@ pub fn main() {
     // Name of the camera uniform
     let transform_matrix_name = CString::new("transform_matrix").unwrap();
 
-    let mut head_horiz_angle: f32 = 0.;
-    let mut head_vert_angle:  f32 = (-89.9999999f32).to_radians();
-    let mut move_speed:       f32 = 10.;
+    let mut camera = Camera::new(head_pos);
+    let mut move_speed = MOVE_SPEED;
 
     // Find the transform matrix location
     let transform_matrix_loc = unsafe {
@@ -552,31 +1004,47 @@ pub fn main() {
         gl::GetUniformLocation(line_program, transform_matrix_name.as_ptr())
     };
 
-    let update_transforms = |origin: &mut Point3<f32>, horiz_angle: f32, vert_angle: f32, movement_front: f32, movement_strafe: f32| {
-        let direction = Vector3::new(
-            vert_angle.cos() * horiz_angle.sin(),
-            vert_angle.sin(),
-            vert_angle.cos() * horiz_angle.cos());
-        
-        let horiz_angle = horiz_angle + std::f32::consts::PI / 2.;
-        let vert_angle  = 0f32;
-        let direction_strafe = Vector3::new(
-            vert_angle.cos() * horiz_angle.sin(),
-            vert_angle.sin(),
-            vert_angle.cos() * horiz_angle.cos());
-
-        *origin += direction * movement_front;
-        *origin += direction_strafe * movement_strafe;
-        
-        let proj_matrix: Matrix4<f32> =
-            perspective(Deg(45.),
-                win_width.get() as f32 / win_height.get() as f32,
-                0.01, 2000000.0);
-        let view_matrix: Matrix4<f32> =
-            Matrix4::look_at(*origin, *origin + direction, Vector3::new(0., 1., 0.));
-        let transform_matrix = proj_matrix * view_matrix;
-        
-        print!("Character at {:?}\n", origin);
+    // Update the colour mode uniforms of the programs using the line
+    // fragment shader
+    let color_by_name = CString::new("color_by").unwrap();
+    let colormap_name = CString::new("colormap").unwrap();
+    let set_color_mode = |color_by: ColorBy, colormap: Colormap| unsafe {
+        for &prog in &[line_program, legend_program] {
+            gl::UseProgram(prog);
+            gl::Uniform1i(gl::GetUniformLocation(prog, color_by_name.as_ptr()),
+                          color_by.uniform());
+            gl::Uniform1i(gl::GetUniformLocation(prog, colormap_name.as_ptr()),
+                          colormap.uniform());
+        }
+    };
+    set_color_mode(color_by, colormap);
+
+    // Update the slope uniforms of the triangle program
+    let uniform = |name: &str| unsafe {
+        gl::GetUniformLocation(program, CString::new(name).unwrap().as_ptr())
+    };
+    let climbable_slope_loc = uniform("climbable_slope");
+    let inverted_slope_loc  = uniform("inverted_slope");
+    let ramps_loc           = uniform("ramps");
+    let slope_colormap_loc  = uniform("slope_colormap");
+    let set_slopes = |preset: &Preset| unsafe {
+        gl::UseProgram(program);
+        gl::Uniform1f(climbable_slope_loc, preset.climbable);
+        gl::Uniform1f(inverted_slope_loc, preset.inverted);
+        gl::Uniform3fv(ramps_loc, 6, preset.ramps.as_ptr() as *const GLfloat);
+        gl::Uniform1i(slope_colormap_loc,
+                      preset.colormap.map(|x| x.uniform()).unwrap_or(-1));
+    };
+    set_slopes(slopes.preset());
+
+    let pixel_scale_loc = unsafe {
+        gl::GetUniformLocation(legend_program,
+                               CString::new("pixel_scale").unwrap().as_ptr())
+    };
+
+    let update_transforms = |camera: &Camera| {
+        let transform_matrix = camera.matrix(
+            win_width.get() as f32 / win_height.get() as f32);
 
         // Update the uniform
         unsafe {
@@ -592,7 +1060,7 @@ pub fn main() {
     };
     
     // Update initial transform state
-    update_transforms(&mut head_pos, head_horiz_angle, head_vert_angle, 0., 0.);
+    update_transforms(&camera);
 
     // Enables movement of the camera angle by the mouse
     let mut mouse_enabled = true;
@@ -606,9 +1074,64 @@ pub fn main() {
     // Start a timer
     let start = Instant::now();
     let mut last_status = start;
+    let mut last_frame = start;
     let mut frames = 0;
+    let mut playback_data: Vec<PathVertex> = Vec::new();
+    let mut pick_data: Vec<PathVertex> = Vec::new();
+    let mut picked: Option<Pick> = None;
+    let mut hud_data: Vec<PathVertex> = Vec::new();
+    let mut hud_visible = true;
+    let mut fps = 0f64;
     'running: loop {
+        // Move the playback on by the time since the last frame
+        let now = Instant::now();
+        let frame_time = (now - last_frame).as_secs_f32();
+        playback.advance(frame_time as f64);
+        last_frame = now;
+        if playback.active && playback.playing {
+            frame_changed = true;
+        }
+
+        if playback.active && frame_changed {
+            // Keep the camera looking at the marker from a distance
+            if let (true, Some(marker)) = (playback.follow, playback.marker()) {
+                let target = Point3::new(marker.y as f32,
+                                         marker.z as f32 + PATH_HEIGHT,
+                                         marker.x as f32);
+                camera.follow(target, FOLLOW_DISTANCE);
+                update_transforms(&camera);
+            }
+
+            playback_data = playback_vertices(&playback);
+            unsafe {
+                gl::BindBuffer(gl::ARRAY_BUFFER, playback_buffer);
+                gl::BufferData(
+                    gl::ARRAY_BUFFER,
+                    core::mem::size_of_val(&playback_data[..]) as isize,
+                    playback_data.as_ptr() as *const _,
+                    gl::STREAM_DRAW,
+                );
+            }
+        }
+
         if focused && frame_changed {
+            if hud_visible {
+                let lines = hud_lines(&camera, move_speed, fps,
+                                      (triangles.len(), vertex_data.len()),
+                                      picked.as_ref(), slopes.preset(),
+                                      &playback);
+                hud_data = hud::vertices(&lines, win_width.get() as f32);
+                unsafe {
+                    gl::BindBuffer(gl::ARRAY_BUFFER, hud_buffer);
+                    gl::BufferData(
+                        gl::ARRAY_BUFFER,
+                        core::mem::size_of_val(&hud_data[..]) as isize,
+                        hud_data.as_ptr() as *const _,
+                        gl::STREAM_DRAW,
+                    );
+                }
+            }
+
             unsafe {
                 gl::ClearColor(0.0, 0.0, 0.0, 1.0);
                 gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
@@ -618,9 +1141,61 @@ pub fn main() {
                 gl::DrawElements(gl::TRIANGLES, triangles.len() as i32 * 3,
                     gl::UNSIGNED_INT, core::ptr::null_mut());
                 
+                // Lines can fade out
+                gl::Enable(gl::BLEND);
+                gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
                 gl::UseProgram(line_program);
                 gl::BindVertexArray(path_vao);
                 gl::DrawArrays(gl::LINES, 0, path_data.len() as i32);
+                if playback.active {
+                    gl::BindVertexArray(playback_vao);
+                    gl::DrawArrays(gl::LINES, 0, playback_data.len() as i32);
+                }
+                gl::Disable(gl::BLEND);
+
+                // Outline the picked triangle through anything in front of it
+                if !pick_data.is_empty() {
+                    gl::Disable(gl::DEPTH_TEST);
+                    gl::BindVertexArray(pick_vao);
+                    gl::DrawArrays(gl::LINES, 0, pick_data.len() as i32);
+                    gl::Enable(gl::DEPTH_TEST);
+                }
+
+                // Draw the legend over everything, the ramp is only
+                // meaningful when colouring by value
+                if !args.paths.is_empty() {
+                    gl::Disable(gl::DEPTH_TEST);
+                    gl::Disable(gl::CULL_FACE);
+                    gl::UseProgram(legend_program);
+                    gl::Uniform2f(pixel_scale_loc,
+                                  2. / win_width.get() as f32,
+                                  2. / win_height.get() as f32);
+                    gl::BindVertexArray(legend_vao);
+                    let count = if color_by == ColorBy::File {
+                        legend_swatches
+                    } else {
+                        legend_data.len()
+                    };
+                    gl::DrawArrays(gl::TRIANGLES, 0, count as i32);
+                    gl::Enable(gl::DEPTH_TEST);
+                    gl::Enable(gl::CULL_FACE);
+                }
+
+                // Draw the HUD over everything, on a see-through background
+                if hud_visible {
+                    gl::Disable(gl::DEPTH_TEST);
+                    gl::Disable(gl::CULL_FACE);
+                    gl::Enable(gl::BLEND);
+                    gl::UseProgram(legend_program);
+                    gl::Uniform2f(pixel_scale_loc,
+                                  2. / win_width.get() as f32,
+                                  2. / win_height.get() as f32);
+                    gl::BindVertexArray(hud_vao);
+                    gl::DrawArrays(gl::TRIANGLES, 0, hud_data.len() as i32);
+                    gl::Disable(gl::BLEND);
+                    gl::Enable(gl::DEPTH_TEST);
+                    gl::Enable(gl::CULL_FACE);
+                }
             }
 
             // Set that the frame has not changed
@@ -635,14 +1210,14 @@ pub fn main() {
 
         if last_status.elapsed().as_secs_f64() >= 1.0 {
             let elapsed = last_status.elapsed().as_secs_f64();
-            print!("FPS {:10.2} | triangles {:10} | verticies {:10}\n",
-                   frames as f64 / elapsed,
-                   triangles.len(),
-                   vertex_data.len());
+            fps = frames as f64 / elapsed;
 
             // Reset frame counter
             frames = 0;
             last_status = Instant::now();
+
+            // Show the new rate
+            frame_changed |= hud_visible;
         }
 
         // Check for events
@@ -668,57 +1243,187 @@ pub fn main() {
                     }
                     
                     // Update transforms
-                    update_transforms(&mut head_pos, head_horiz_angle, head_vert_angle, 0., 0.);
+                    update_transforms(&camera);
                     frame_changed = true;
                 }
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     sdl_context.mouse().set_relative_mouse_mode(false);
                     mouse_enabled = false;
                 },
-                Event::KeyDown { keycode: Some(Keycode::W), .. } => {
-                    update_transforms(&mut head_pos, head_horiz_angle, head_vert_angle, move_speed, 0.);
+                Event::KeyDown { keycode: Some(Keycode::C), .. } => {
+                    color_by = color_by.next();
+                    set_color_mode(color_by, colormap);
+                    print_legend(&args.paths, &file_colors, color_by,
+                                 colormap, &ranges);
                     frame_changed = true;
                 },
-                Event::KeyDown { keycode: Some(Keycode::S), .. } => {
-                    update_transforms(&mut head_pos, head_horiz_angle, head_vert_angle, -move_speed, 0.);
+                Event::KeyDown { keycode: Some(Keycode::V), .. } => {
+                    colormap = colormap.next();
+                    set_color_mode(color_by, colormap);
+                    print_legend(&args.paths, &file_colors, color_by,
+                                 colormap, &ranges);
                     frame_changed = true;
                 },
-                Event::KeyDown { keycode: Some(Keycode::A), .. } => {
-                    update_transforms(&mut head_pos, head_horiz_angle, head_vert_angle, 0., move_speed);
+                Event::KeyDown { keycode: Some(Keycode::H), .. } => {
+                    hud_visible = !hud_visible;
                     frame_changed = true;
-                },
-                Event::KeyDown { keycode: Some(Keycode::D), .. } => {
-                    update_transforms(&mut head_pos, head_horiz_angle, head_vert_angle, 0., -move_speed);
+                }
+                Event::KeyDown { keycode: Some(key @ Keycode::B), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Minus), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Equals), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Num9), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Num0), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::G), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::R), .. } => {
+                    match key {
+                        Keycode::B      => slopes.next(),
+                        Keycode::Minus  => slopes.adjust(-1., 0.),
+                        Keycode::Equals => slopes.adjust(1., 0.),
+                        Keycode::Num9   => slopes.adjust(0., -1.),
+                        Keycode::Num0   => slopes.adjust(0., 1.),
+                        Keycode::G      => slopes.toggle_colormap(colormap),
+                        _ => {
+                            // Keep the old presets if the file is broken
+                            if let Err(err) = slopes.reload() {
+                                print!("{}\n", err);
+                            }
+                        }
+                    }
+                    print!("{}\n", slopes.preset().describe());
+                    set_slopes(slopes.preset());
                     frame_changed = true;
-                },
-                Event::MouseWheel { y, .. } => {
-                    if y > 0 {
-                        move_speed *= 1.2;
+                }
+                Event::KeyDown { keycode: Some(key @ Keycode::P), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Space), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Left), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Right), .. } |
+                Event::KeyDown {
+                    keycode: Some(key @ Keycode::LeftBracket), ..
+                } |
+                Event::KeyDown {
+                    keycode: Some(key @ Keycode::RightBracket), ..
+                } |
+                Event::KeyDown { keycode: Some(key @ Keycode::N), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::F), .. } => {
+                    // Playback controls only work while it's shown
+                    if key != Keycode::P && !playback.active {
+                        continue;
+                    }
+                    match key {
+                        Keycode::P     => playback.toggle(),
+                        Keycode::Space => playback.play_pause(),
+                        Keycode::Left  => playback.scrub(-SCRUB_SECONDS),
+                        Keycode::Right => playback.scrub(SCRUB_SECONDS),
+                        Keycode::LeftBracket  => playback.change_speed(0.5),
+                        Keycode::RightBracket => playback.change_speed(2.),
+                        Keycode::N     => playback.next_recording(),
+                        _ => playback.follow = !playback.follow,
+                    }
+                    if playback.active {
+                        print!("{}\n", playback.status());
                     } else {
-                        move_speed /= 1.2;
+                        print!("Playback hidden\n");
                     }
+                    frame_changed = true;
+                }
+                Event::KeyDown { keycode: Some(key @ Keycode::Num1), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Num2), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::Num3), .. } => {
+                    let mode = match key {
+                        Keycode::Num1 => Mode::FreeFly,
+                        Keycode::Num2 => Mode::Orbit,
+                        _             => Mode::TopDown,
+                    };
+
+                    // Orbit around whatever is in the middle of the view
+                    camera.set_mode(mode, |origin, dir| {
+                        bvh.raycast(origin, dir).map(|x| x.1)
+                    });
+                    print!("{} camera\n", mode.name());
+                    update_transforms(&camera);
+                    frame_changed = true;
+                }
+                Event::MouseWheel { y, .. } => {
+                    camera.wheel(y > 0, &mut move_speed);
+                    update_transforms(&camera);
+                    frame_changed = true;
                 }
                 Event::MouseButtonDown { mouse_btn: MouseButton::Left, .. } => {
                     sdl_context.mouse().set_relative_mouse_mode(true);
                     mouse_enabled = true;
                 },
-                Event::MouseMotion { xrel, yrel, .. } => {
-                    if mouse_enabled {
-                        let xdel = xrel as f32 / 400.;
-                        let ydel = yrel as f32 / 400.;
-                        head_horiz_angle = head_horiz_angle - xdel;
-                        head_vert_angle  = (head_vert_angle  - ydel)
-                            .min(std::f32::consts::PI / 2. - 0.0001)
-                            .max(-std::f32::consts::PI / 2. + 0.0001);
-                        update_transforms(&mut head_pos,
-                            head_horiz_angle, head_vert_angle, 0., 0.);
-                        frame_changed = true;
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Right, x, y, ..
+                } => {
+                    // Pick under the crosshair in the middle of the view
+                    // while looking with the mouse, otherwise under the
+                    // cursor
+                    let (width, height) =
+                        (win_width.get() as f32, win_height.get() as f32);
+                    let (x, y) = if mouse_enabled {
+                        (0., 0.)
+                    } else {
+                        (2. * x as f32 / width - 1., 1. - 2. * y as f32 / height)
+                    };
+                    let (origin, dir) = camera.ray(x, y, width / height);
+                    picked = bvh.pick(origin, dir);
+                    match &picked {
+                        Some(pick) => {
+                            print_pick(pick, slopes.preset());
+                            pick_data = pick_vertices(pick);
+                        }
+                        None => {
+                            print!("Nothing to pick\n");
+                            pick_data.clear();
+                        }
                     }
+                    unsafe {
+                        gl::BindBuffer(gl::ARRAY_BUFFER, pick_buffer);
+                        gl::BufferData(
+                            gl::ARRAY_BUFFER,
+                            core::mem::size_of_val(&pick_data[..]) as isize,
+                            pick_data.as_ptr() as *const _,
+                            gl::DYNAMIC_DRAW,
+                        );
+                    }
+                    frame_changed = true;
+                }
+                Event::MouseMotion { xrel, yrel, .. } if mouse_enabled => {
+                    camera.mouse_motion(xrel as f32, yrel as f32,
+                                        win_height.get() as f32);
+                    update_transforms(&camera);
+                    frame_changed = true;
                 }
                 _ => {}
             }
         }
 
+        // Move by the keys held down, scaled by the frame time so the speed
+        // doesn't depend on the frame rate
+        let keys = event_pump.keyboard_state();
+        let axis = |pos: Scancode, neg: Scancode| {
+            keys.is_scancode_pressed(pos) as i32 as f32 -
+                keys.is_scancode_pressed(neg) as i32 as f32
+        };
+        let movement = Vector3::new(axis(Scancode::W, Scancode::S),
+                                    axis(Scancode::A, Scancode::D),
+                                    axis(Scancode::E, Scancode::Q));
+        if movement != Vector3::new(0., 0., 0.) {
+            let mut speed = move_speed * frame_time;
+            if keys.is_scancode_pressed(Scancode::LShift) {
+                speed *= SPRINT_FACTOR;
+            }
+            if keys.is_scancode_pressed(Scancode::LCtrl) {
+                speed *= CRAWL_FACTOR;
+            }
+
+            // Diagonals are no faster than moving along one axis
+            let movement = movement.normalize() * speed;
+            camera.translate(movement.x, movement.y, movement.z);
+            update_transforms(&camera);
+            frame_changed = true;
+        }
+
         // Cap at 300 fps
         std::thread::sleep(
             std::time::Duration::from_nanos(1_000_000_000 / 300));
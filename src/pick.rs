@@ -0,0 +1,353 @@
+//! Finding the mesh triangle along a ray, with a bounding volume hierarchy
+//! so it's quick enough to do while the mouse moves
+
+use cgmath::{Point3, Vector3, InnerSpace};
+
+use falkvbo::Vertex;
+
+/// Most triangles in a leaf of the hierarchy
+const LEAF_SIZE: usize = 4;
+
+/// Get the distance along the ray from `origin` in the normalized direction
+/// `dir` to where it hits the triangle `tri`, from either side
+pub fn ray_triangle(origin: Point3<f32>, dir: Vector3<f32>,
+                    tri: [Point3<f32>; 3]) -> Option<f32> {
+    // Moller-Trumbore
+    let edge1 = tri[1] - tri[0];
+    let edge2 = tri[2] - tri[0];
+    let pvec  = dir.cross(edge2);
+    let det   = edge1.dot(pvec);
+    if det.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let inv_det = 1. / det;
+    let tvec = origin - tri[0];
+    let u = tvec.dot(pvec) * inv_det;
+    if !(0. ..=1.).contains(&u) {
+        return None;
+    }
+
+    let qvec = tvec.cross(edge1);
+    let v = dir.dot(qvec) * inv_det;
+    if v < 0. || u + v > 1. {
+        return None;
+    }
+
+    let dist = edge2.dot(qvec) * inv_det;
+    if dist > 0. { Some(dist) } else { None }
+}
+
+/// Get the corners of triangle `tri`
+pub fn corners(vertices: &[Vertex], tri: [u32; 3]) -> [Point3<f32>; 3] {
+    tri.map(|idx| {
+        let Vertex(x, y, z) = vertices[idx as usize];
+        Point3::new(x, y, z)
+    })
+}
+
+/// A triangle hit by a ray
+#[derive(Debug, Clone, Copy)]
+pub struct Pick {
+    /// Index of the triangle
+    pub triangle: usize,
+
+    /// Corners of the triangle
+    pub corners: [Point3<f32>; 3],
+
+    /// Where the ray hit it
+    pub point: Point3<f32>,
+
+    /// Normal of the triangle's front face
+    pub normal: Vector3<f32>,
+
+    /// Angle between the front face and level ground, in degrees
+    pub slope: f32,
+}
+
+/// A node of the hierarchy
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    /// Bounds of all triangles under the node
+    min: [f32; 3],
+    max: [f32; 3],
+
+    /// For leaves the range of `Bvh::order` in the node. Inner nodes have a
+    /// `count` of 0, their first child follows them and `start` is the index
+    /// of the second.
+    start: u32,
+    count: u32,
+}
+
+/// A bounding volume hierarchy over the triangles of a mesh
+pub struct Bvh<'a> {
+    /// The mesh
+    vertices:  &'a [Vertex],
+    triangles: &'a [[u32; 3]],
+
+    /// Nodes, the root first
+    nodes: Vec<Node>,
+
+    /// Triangle indicies, with those of each leaf together
+    order: Vec<u32>,
+}
+
+/// Get the bounds and centroid of a triangle
+fn triangle_bounds(tri: [Point3<f32>; 3]) -> ([f32; 3], [f32; 3], [f32; 3]) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    let mut centroid = [0.; 3];
+    for point in &tri {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(point[axis]);
+            max[axis] = max[axis].max(point[axis]);
+            centroid[axis] += point[axis] / 3.;
+        }
+    }
+    (min, max, centroid)
+}
+
+/// Build the nodes for the triangles in `order`, which start at `offset` in
+/// the full order, splitting them at the median centroid along the longest
+/// axis
+fn build(nodes: &mut Vec<Node>, bounds: &[([f32; 3], [f32; 3], [f32; 3])],
+         order: &mut [u32], offset: usize) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    let mut cmin = [f32::MAX; 3];
+    let mut cmax = [f32::MIN; 3];
+    for &tri in order.iter() {
+        let (tmin, tmax, centroid) = &bounds[tri as usize];
+        for axis in 0..3 {
+            min[axis]  = min[axis].min(tmin[axis]);
+            max[axis]  = max[axis].max(tmax[axis]);
+            cmin[axis] = cmin[axis].min(centroid[axis]);
+            cmax[axis] = cmax[axis].max(centroid[axis]);
+        }
+    }
+
+    let idx = nodes.len();
+    nodes.push(Node {
+        min, max,
+        start: offset as u32,
+        count: order.len() as u32,
+    });
+
+    let axis = (0..3).max_by(|&a, &b| {
+        (cmax[a] - cmin[a]).partial_cmp(&(cmax[b] - cmin[b])).unwrap()
+    }).unwrap();
+    if order.len() <= LEAF_SIZE || cmax[axis] <= cmin[axis] {
+        return;
+    }
+
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |&a, &b| {
+        bounds[a as usize].2[axis].partial_cmp(&bounds[b as usize].2[axis])
+            .unwrap()
+    });
+    let (left, right) = order.split_at_mut(mid);
+    build(nodes, bounds, left, offset);
+    let second = nodes.len();
+    build(nodes, bounds, right, offset + mid);
+
+    nodes[idx].start = second as u32;
+    nodes[idx].count = 0;
+}
+
+/// Check if the ray from `origin` with inverted direction `inv_dir` hits the
+/// box of `node` before `limit`
+fn hits_node(node: &Node, origin: Point3<f32>, inv_dir: [f32; 3],
+             limit: f32) -> bool {
+    let mut near = 0f32;
+    let mut far  = limit;
+    for axis in 0..3 {
+        let t0 = (node.min[axis] - origin[axis]) * inv_dir[axis];
+        let t1 = (node.max[axis] - origin[axis]) * inv_dir[axis];
+        near = near.max(t0.min(t1));
+        far  = far.min(t0.max(t1));
+    }
+    near <= far
+}
+
+impl<'a> Bvh<'a> {
+    /// Build the hierarchy for a mesh
+    pub fn new(vertices: &'a [Vertex], triangles: &'a [[u32; 3]]) -> Self {
+        let bounds: Vec<_> = triangles.iter()
+            .map(|&tri| triangle_bounds(corners(vertices, tri))).collect();
+        let mut order: Vec<u32> = (0..triangles.len() as u32).collect();
+        let mut nodes = Vec::with_capacity(triangles.len() / LEAF_SIZE * 2 + 1);
+        if !triangles.is_empty() {
+            build(&mut nodes, &bounds, &mut order, 0);
+        }
+        Bvh { vertices, triangles, nodes, order }
+    }
+
+    /// Find the closest triangle hit by the ray from `origin` in direction
+    /// `dir`, returning its index and the distance to it
+    pub fn raycast(&self, origin: Point3<f32>, dir: Vector3<f32>)
+            -> Option<(usize, f32)> {
+        let dir = dir.normalize();
+        let inv_dir = [1. / dir.x, 1. / dir.y, 1. / dir.z];
+
+        let mut best: Option<(usize, f32)> = None;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(idx) = stack.pop() {
+            let node  = &self.nodes[idx];
+            let limit = best.map(|x| x.1).unwrap_or(f32::INFINITY);
+            if !hits_node(node, origin, inv_dir, limit) {
+                continue;
+            }
+
+            if node.count == 0 {
+                stack.push(node.start as usize);
+                stack.push(idx + 1);
+                continue;
+            }
+
+            let start = node.start as usize;
+            for &tri in &self.order[start..start + node.count as usize] {
+                let tri = tri as usize;
+                let corners = corners(self.vertices, self.triangles[tri]);
+                if let Some(dist) = ray_triangle(origin, dir, corners) {
+                    if best.map(|x| dist < x.1).unwrap_or(true) {
+                        best = Some((tri, dist));
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Pick the closest triangle hit by the ray from `origin` in direction
+    /// `dir`
+    pub fn pick(&self, origin: Point3<f32>, dir: Vector3<f32>)
+            -> Option<Pick> {
+        let (triangle, dist) = self.raycast(origin, dir)?;
+        let corners = corners(self.vertices, self.triangles[triangle]);
+
+        // The same normal and slope as the geometry shader
+        let normal = -(corners[0] - corners[1]).cross(corners[2] - corners[1])
+            .normalize();
+        let slope = normal.y.clamp(-1., 1.).acos().to_degrees();
+        Some(Pick {
+            triangle,
+            corners,
+            point: origin + dir.normalize() * dist,
+            normal,
+            slope,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Get a xorshift random number generator giving floats from -1 to 1
+    fn rng(mut seed: u64) -> impl FnMut() -> f32 {
+        move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed >> 40) as f32 / (1u64 << 23) as f32 - 1.
+        }
+    }
+
+    /// Find the closest triangle hit by a ray by checking all of them
+    fn brute_force(vertices: &[Vertex], triangles: &[[u32; 3]],
+                   origin: Point3<f32>, dir: Vector3<f32>) -> Option<f32> {
+        triangles.iter().filter_map(|&tri| {
+            ray_triangle(origin, dir.normalize(), corners(vertices, tri))
+        }).min_by(|a, b| a.total_cmp(b))
+    }
+
+    #[test]
+    fn raycast_matches_brute_force() {
+        // Bumpy terrain with random winding, so rays hit both sides
+        let mut rand = rng(0x1234_5678_9abc_def1);
+        let mut vertices = Vec::new();
+        for xx in 0..20 {
+            for zz in 0..20 {
+                vertices.push(Vertex(xx as f32, rand() * 3., zz as f32));
+            }
+        }
+        let mut triangles = Vec::new();
+        for xx in 0..19u32 {
+            for zz in 0..19u32 {
+                let idx = xx * 20 + zz;
+                for &tri in &[[idx, idx + 1, idx + 20],
+                              [idx + 1, idx + 21, idx + 20]] {
+                    triangles.push(if rand() < 0. {
+                        tri
+                    } else {
+                        [tri[0], tri[2], tri[1]]
+                    });
+                }
+            }
+        }
+
+        let bvh = Bvh::new(&vertices, &triangles);
+        assert!(bvh.nodes.len() > 1);
+        let mut hits = 0;
+        for ii in 0..2000 {
+            let origin = Point3::new(rand() * 12. + 9.5, rand() * 10.,
+                                     rand() * 12. + 9.5);
+            let dir = match ii % 4 {
+                // Straight down and up, which have infinite `inv_dir`s
+                0 => Vector3::new(0., -1., 0.),
+                1 => Vector3::new(0., 1., 0.),
+                _ => Vector3::new(rand(), rand(), rand()),
+            };
+
+            let expected = brute_force(&vertices, &triangles, origin, dir);
+            let got = bvh.raycast(origin, dir);
+            assert_eq!(got.map(|x| x.1), expected, "ray {}", ii);
+            if let Some((tri, dist)) = got {
+                assert_eq!(ray_triangle(origin, dir.normalize(),
+                                        corners(&vertices, triangles[tri])),
+                           Some(dist));
+                hits += 1;
+            }
+        }
+        assert!(hits > 500 && hits < 2000, "{} hits", hits);
+
+        assert!(Bvh::new(&vertices, &[])
+                .raycast(Point3::new(0., 1., 0.), -Vector3::unit_y())
+                .is_none());
+    }
+
+    #[test]
+    fn equal_centroids_make_a_leaf() {
+        // Triangles around the same centre at different heights, so they
+        // can't be split
+        let mut vertices = Vec::new();
+        let mut triangles = Vec::new();
+        for ii in 0..10u32 {
+            let size = ii as f32 + 1.;
+            vertices.extend_from_slice(&[
+                Vertex(-size, 0., -size),
+                Vertex(size * 2., 0., -size),
+                Vertex(-size, 0., size * 2.),
+            ]);
+            triangles.push([ii * 3, ii * 3 + 1, ii * 3 + 2]);
+        }
+
+        let bvh = Bvh::new(&vertices, &triangles);
+        assert_eq!(bvh.nodes.len(), 1);
+        assert_eq!(bvh.nodes[0].count, 10);
+
+        let origin = Point3::new(1., 5., 1.);
+        let dir = Vector3::new(0., -1., 0.);
+        let (tri, dist) = bvh.raycast(origin, dir).unwrap();
+        let corners = corners(&vertices, triangles[tri]);
+        assert_eq!((dist, ray_triangle(origin, dir, corners)), (5., Some(5.)));
+
+        // The same triangles from below
+        let origin = Point3::new(1., -5., 1.);
+        assert_eq!(bvh.raycast(origin, -dir).map(|x| x.1), Some(5.));
+        assert!(bvh.raycast(Point3::new(30., 5., 30.), dir).is_none());
+    }
+}
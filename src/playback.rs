@@ -0,0 +1,198 @@
+//! Animated playback of recorded paths
+
+use parse_ealogpos::{Interpolation, Position, Positions, SegmentOptions};
+
+/// Length of the fading trail behind the marker, in seconds of the recording
+const TRAIL_SECONDS: f64 = 10.;
+
+/// Number of lines the trail is drawn with
+const TRAIL_STEPS: usize = 100;
+
+/// How far a scrub moves, in seconds of the recording
+pub const SCRUB_SECONDS: f64 = 5.;
+
+/// Slowest and fastest playback speeds, as multiples of real time
+const MIN_SPEED: f64 = 1. / 16.;
+const MAX_SPEED: f64 = 256.;
+
+/// A recording which can be played back
+pub struct Recording {
+    /// Name to show for the recording
+    pub name: String,
+
+    /// The samples of the recording
+    positions: Positions,
+
+    /// Start and end times of the continuous segments, the trail doesn't
+    /// reach across the breaks between them
+    segments: Vec<(f64, f64)>,
+}
+
+impl Recording {
+    /// Create a recording of `positions` split into segments with `options`,
+    /// or `None` if there is nothing to play
+    pub fn new(name: String, positions: Positions, options: &SegmentOptions)
+            -> Option<Self> {
+        if positions.positions.is_empty() {
+            return None;
+        }
+
+        let segments = positions.segments(options).iter().map(|x| {
+            (x.positions[0].time, x.positions[x.positions.len() - 1].time)
+        }).collect();
+        Some(Recording { name, positions, segments })
+    }
+
+    /// Time of the first sample
+    pub fn start(&self) -> f64 {
+        self.positions.positions[0].time
+    }
+
+    /// Time of the last sample
+    pub fn end(&self) -> f64 {
+        self.positions.positions[self.positions.positions.len() - 1].time
+    }
+}
+
+/// State of the playback of a set of recordings, one at a time
+pub struct Playback {
+    /// The recordings which can be played
+    recordings: Vec<Recording>,
+
+    /// Index of the recording being played
+    current: usize,
+
+    /// Position in the recording, in its own time
+    pub time: f64,
+
+    /// Playback speed as a multiple of real time
+    pub speed: f64,
+
+    /// Set if time is moving
+    pub playing: bool,
+
+    /// Set if the camera follows the marker
+    pub follow: bool,
+
+    /// Set if the marker and trail are shown
+    pub active: bool,
+}
+
+impl Playback {
+    /// Create a stopped playback of `recordings`
+    pub fn new(recordings: Vec<Recording>) -> Self {
+        let time = recordings.first().map(|x| x.start()).unwrap_or(0.);
+        Playback {
+            recordings,
+            current: 0,
+            time,
+            speed: 1.,
+            playing: false,
+            follow: false,
+            active: false,
+        }
+    }
+
+    /// Get the recording being played, if there are any
+    pub fn recording(&self) -> Option<&Recording> {
+        self.recordings.get(self.current)
+    }
+
+    /// Show or hide the playback, it starts out paused
+    pub fn toggle(&mut self) {
+        self.active  = !self.active && !self.recordings.is_empty();
+        self.playing = false;
+    }
+
+    /// Start or stop time, restarting from the beginning if at the end
+    pub fn play_pause(&mut self) {
+        if let Some(recording) = self.recording() {
+            if !self.playing && self.time >= recording.end() {
+                self.time = recording.start();
+            }
+            self.playing = !self.playing;
+        }
+    }
+
+    /// Move time on by `elapsed` real seconds, stopping at the end
+    pub fn advance(&mut self, elapsed: f64) {
+        if !self.active || !self.playing {
+            return;
+        }
+
+        let end = match self.recording() {
+            Some(recording) => recording.end(),
+            None => return,
+        };
+        self.time += elapsed * self.speed;
+        if self.time >= end {
+            self.time    = end;
+            self.playing = false;
+        }
+    }
+
+    /// Jump `seconds` of the recording forwards or backwards
+    pub fn scrub(&mut self, seconds: f64) {
+        if let Some(recording) = self.recording() {
+            self.time = (self.time + seconds)
+                .clamp(recording.start(), recording.end());
+        }
+    }
+
+    /// Multiply the playback speed by `factor`
+    pub fn change_speed(&mut self, factor: f64) {
+        self.speed = (self.speed * factor).clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    /// Switch to the next recording, from its start
+    pub fn next_recording(&mut self) {
+        if self.recordings.is_empty() {
+            return;
+        }
+        self.current = (self.current + 1) % self.recordings.len();
+        self.time    = self.recordings[self.current].start();
+        self.playing = false;
+    }
+
+    /// Get the position of the marker
+    pub fn marker(&self) -> Option<Position> {
+        self.recording()?.positions.sample_at(self.time, Interpolation::Linear)
+    }
+
+    /// Get the points of the trail behind the marker, oldest first, along
+    /// with how faded each is from 0 (gone) to 1 (at the marker)
+    pub fn trail(&self) -> Vec<(Position, f32)> {
+        let recording = match self.recording() {
+            Some(recording) => recording,
+            None => return Vec::new(),
+        };
+
+        // Only go back to the start of the current segment
+        let (seg_start, seg_end) = recording.segments.iter()
+            .rev().find(|x| x.0 <= self.time).copied()
+            .unwrap_or((recording.start(), recording.start()));
+        let end   = self.time.min(seg_end);
+        let start = (self.time - TRAIL_SECONDS).max(seg_start).min(end);
+
+        (0..=TRAIL_STEPS).filter_map(|step| {
+            let time = start + (end - start) * step as f64 /
+                TRAIL_STEPS as f64;
+            let fade = 1. - (self.time - time) / TRAIL_SECONDS;
+            recording.positions.sample_at(time, Interpolation::Linear)
+                .map(|x| (x, fade as f32))
+        }).collect()
+    }
+
+    /// Get a description of the playback state
+    pub fn status(&self) -> String {
+        match self.recording() {
+            Some(recording) => format!(
+                "{} {} at {:.1} / {:.1} s, {}x{}",
+                if self.playing { "Playing" } else { "Paused" },
+                recording.name, self.time - recording.start(),
+                recording.end() - recording.start(), self.speed,
+                if self.follow { ", following" } else { "" }),
+            None => "Nothing to play".into(),
+        }
+    }
+}
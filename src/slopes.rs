@@ -0,0 +1,364 @@
+//! Slope thresholds and the colours triangles are drawn with, in named
+//! presets which can be extended from a settings file
+//!
+//! A settings file has a section per preset, starting from the built-in
+//! preset of the same name if there is one and the default otherwise:
+//!
+//! ```text
+//! # Source engine, with a gradient
+//! [source]
+//! climbable        = 45.57
+//! inverted         = 90
+//! climbable-ramp   = #e6e6e6 #1a1a1a
+//! unclimbable-ramp = #ccccff #00004d
+//! inverted-ramp    = #ffcccc #4d0000
+//! colormap         = viridis
+//! ```
+
+use std::path::PathBuf;
+
+use crate::colors::Colormap;
+
+/// How a triangle can be walked on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    /// Shallow enough to walk up
+    Climbable,
+
+    /// Too steep to walk up
+    Unclimbable,
+
+    /// Facing down, like a ceiling
+    Inverted,
+}
+
+impl Class {
+    /// Lowercase name of the class
+    pub fn name(self) -> &'static str {
+        match self {
+            Class::Climbable   => "climbable",
+            Class::Unclimbable => "unclimbable",
+            Class::Inverted    => "inverted",
+        }
+    }
+}
+
+/// Slope thresholds and colours
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    /// Name to pick the preset by
+    pub name: String,
+
+    /// Steepest slope which can be walked up, in degrees
+    pub climbable: f32,
+
+    /// Slope in degrees past which triangles face down
+    pub inverted: f32,
+
+    /// Colours at the shallow and steep ends of each class, in `Class` order.
+    /// Channels above 1 stay saturated for part of the ramp.
+    pub ramps: [[[f32; 3]; 2]; 3],
+
+    /// Colormap to colour by slope with rather than by class
+    pub colormap: Option<Colormap>,
+}
+
+/// Ramps the viewer has always drawn with
+const CLASSIC_RAMPS: [[[f32; 3]; 2]; 3] = [
+    [[0.9, 0.9, 0.9], [0.1, 0.1, 0.1]],
+    [[0.8, 0.8, 1.1], [0.0, 0.0, 0.3]],
+    [[1.1, 0.8, 0.8], [0.3, 0.0, 0.0]],
+];
+
+/// Ramps from green through amber to red, for telling classes apart at a
+/// glance
+const TRAFFIC_RAMPS: [[[f32; 3]; 2]; 3] = [
+    [[0.2, 0.8, 0.2], [0.8, 0.8, 0.2]],
+    [[0.9, 0.5, 0.1], [0.6, 0.1, 0.1]],
+    [[0.6, 0.4, 0.8], [0.2, 0.1, 0.3]],
+];
+
+impl Preset {
+    /// Create a preset with the classic colours
+    fn new(name: &str, climbable: f32) -> Self {
+        Preset {
+            name: name.into(),
+            climbable,
+            inverted: 90.,
+            ramps: CLASSIC_RAMPS,
+            colormap: None,
+        }
+    }
+
+    /// Get the class of a triangle with `slope` in degrees
+    pub fn class(&self, slope: f32) -> Class {
+        if slope <= self.climbable {
+            Class::Climbable
+        } else if slope <= self.inverted {
+            Class::Unclimbable
+        } else {
+            Class::Inverted
+        }
+    }
+
+    /// Get a description of the preset
+    pub fn describe(&self) -> String {
+        format!("Slope preset {}: climbable up to {:.2}, inverted past {:.2}{}",
+                self.name, self.climbable, self.inverted,
+                self.colormap.map(|x| format!(", coloured with {}", x.name()))
+                    .unwrap_or_default())
+    }
+}
+
+/// Get the built-in presets, the default first
+pub fn builtin() -> Vec<Preset> {
+    vec![
+        Preset::new("wow", 50.),
+        // Floors need a normal with an up component of at least 0.7
+        Preset::new("source", 0.7f32.acos().to_degrees()),
+        Preset::new("unreal", 44.765),
+        Preset::new("unity", 45.),
+        Preset { ramps: TRAFFIC_RAMPS, ..Preset::new("traffic", 50.) },
+        Preset {
+            colormap: Some(Colormap::Viridis),
+            ..Preset::new("gradient", 50.)
+        },
+    ]
+}
+
+/// Parse a `#rrggbb` colour
+fn parse_color(text: &str) -> Result<[f32; 3], String> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(format!("Invalid colour {:?}", text));
+    }
+
+    let mut ret = [0.; 3];
+    for (channel, ret) in ret.iter_mut().enumerate() {
+        *ret = u8::from_str_radix(&hex[channel * 2..channel * 2 + 2], 16)
+            .map_err(|_| format!("Invalid colour {:?}", text))? as f32 / 255.;
+    }
+    Ok(ret)
+}
+
+/// Parse a settings file, adding its presets to `presets` and replacing
+/// those with the same names
+pub fn parse(text: &str, presets: &mut Vec<Preset>) -> Result<(), String> {
+    let mut current: Option<usize> = None;
+    for (lineno, line) in text.lines().enumerate() {
+        let error = |err: String| format!("line {}: {}", lineno + 1, err);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Start a new preset
+        if let Some(name) = line.strip_prefix('[')
+                .and_then(|x| x.strip_suffix(']')) {
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(error("Empty preset name".into()));
+            }
+            current = Some(match presets.iter().position(|x| x.name == name) {
+                Some(idx) => idx,
+                None => {
+                    presets.push(Preset {
+                        name: name.into(),
+                        ..builtin()[0].clone()
+                    });
+                    presets.len() - 1
+                }
+            });
+            continue;
+        }
+
+        let preset = match current {
+            Some(idx) => &mut presets[idx],
+            None => return Err(error("Setting outside of a preset".into())),
+        };
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| error(format!("Expected key = value, got {:?}",
+                                         line)))?;
+        let (key, value) = (key.trim(), value.trim());
+
+        let angle = || {
+            value.parse::<f32>().ok().filter(|x| (0. ..=180.).contains(x))
+                .ok_or_else(|| error(format!("Invalid angle {:?}", value)))
+        };
+        let ramp = || {
+            let colors = value.split_whitespace().map(parse_color)
+                .collect::<Result<Vec<_>, _>>().map_err(error)?;
+            match colors[..] {
+                [start, end] => Ok([start, end]),
+                _ => Err(error(format!("Expected two colours, got {:?}",
+                                       value))),
+            }
+        };
+
+        match key {
+            "climbable" => preset.climbable = angle()?,
+            "inverted"  => preset.inverted  = angle()?,
+            "climbable-ramp"   => preset.ramps[0] = ramp()?,
+            "unclimbable-ramp" => preset.ramps[1] = ramp()?,
+            "inverted-ramp"    => preset.ramps[2] = ramp()?,
+            "colormap" => {
+                preset.colormap = match value {
+                    "none" => None,
+                    _ => Some(value.parse().map_err(error)?),
+                };
+            }
+            _ => return Err(error(format!("Unknown setting {:?}", key))),
+        }
+    }
+
+    if let Some(preset) = presets.iter().find(|x| x.climbable > x.inverted) {
+        return Err(format!(
+            "Preset {}: climbable slope {} is past the inverted slope {}",
+            preset.name, preset.climbable, preset.inverted));
+    }
+    Ok(())
+}
+
+/// The presets available in the viewer and the one in use
+pub struct Slopes {
+    /// The presets, built-in first
+    presets: Vec<Preset>,
+
+    /// Index of the preset in use
+    current: usize,
+
+    /// Settings file the presets were extended from
+    file: Option<PathBuf>,
+}
+
+impl Slopes {
+    /// Load the built-in presets and those in the settings `file`
+    pub fn new(file: Option<PathBuf>) -> Result<Self, String> {
+        let mut ret = Slopes { presets: Vec::new(), current: 0, file };
+        ret.reload()?;
+        Ok(ret)
+    }
+
+    /// Get the preset in use
+    pub fn preset(&self) -> &Preset {
+        &self.presets[self.current]
+    }
+
+    /// Use the preset called `name`
+    pub fn select(&mut self, name: &str) -> Result<(), String> {
+        self.current = self.presets.iter().position(|x| x.name == name)
+            .ok_or_else(|| format!("Unknown slope preset {:?}, have {}", name,
+                self.presets.iter().map(|x| x.name.as_str())
+                    .collect::<Vec<_>>().join(", ")))?;
+        Ok(())
+    }
+
+    /// Use the preset after this one
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.presets.len();
+    }
+
+    /// Move the thresholds of the preset in use by these many degrees,
+    /// keeping them in order
+    pub fn adjust(&mut self, climbable: f32, inverted: f32) {
+        let preset = &mut self.presets[self.current];
+        preset.inverted  = (preset.inverted + inverted).clamp(0., 180.);
+        preset.climbable = (preset.climbable + climbable)
+            .clamp(0., preset.inverted);
+        preset.inverted  = preset.inverted.max(preset.climbable);
+    }
+
+    /// Switch the preset in use between colouring by class and by slope
+    /// with `colormap`
+    pub fn toggle_colormap(&mut self, colormap: Colormap) {
+        let preset = &mut self.presets[self.current];
+        preset.colormap = match preset.colormap {
+            Some(_) => None,
+            None => Some(colormap),
+        };
+    }
+
+    /// Throw away any changes and load the presets again, staying on the
+    /// same one if it's still there
+    pub fn reload(&mut self) -> Result<(), String> {
+        let mut presets = builtin();
+        if let Some(file) = &self.file {
+            let text = std::fs::read_to_string(file).map_err(|err| {
+                format!("Failed to read {}: {}", file.display(), err)
+            })?;
+            parse(&text, &mut presets)
+                .map_err(|err| format!("{}: {}", file.display(), err))?;
+        }
+
+        let name = self.presets.get(self.current).map(|x| x.name.clone());
+        self.presets = presets;
+        self.current = 0;
+        if let Some(name) = name {
+            let _ = self.select(&name);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_override_builtin_presets() {
+        let mut presets = builtin();
+        parse("\
+# Steeper source floors
+[source]
+climbable      = 60
+inverted-ramp  = #ff0000 #000000
+
+[custom]
+inverted = 120
+colormap = green
+", &mut presets).unwrap();
+
+        assert_eq!(presets.len(), builtin().len() + 1);
+        let source = presets.iter().find(|x| x.name == "source").unwrap();
+        assert_eq!((source.climbable, source.inverted), (60., 90.));
+        assert_eq!(source.ramps[0], CLASSIC_RAMPS[0]);
+        assert_eq!(source.ramps[2], [[1., 0., 0.], [0., 0., 0.]]);
+
+        let custom = presets.last().unwrap();
+        assert_eq!(custom.name, "custom");
+        assert_eq!((custom.climbable, custom.inverted), (50., 120.));
+        assert_eq!(custom.colormap, Some(Colormap::Green));
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        for &(text, err) in &[
+            ("climbable = 3", "line 1: Setting outside of a preset"),
+            ("[a]\n\nclimbable = 200", "line 3: Invalid angle \"200\""),
+            ("[a]\nfoo = 1", "line 2: Unknown setting \"foo\""),
+            ("[a]\nclimbable-ramp = #fff", "line 2: Invalid colour \"#fff\""),
+            ("[a]\ncolormap = jet", "line 2: Unknown colormap \"jet\""),
+            ("# a\n[a]\nnope", "line 3: Expected key = value, got \"nope\""),
+            ("[a]\ninverted = 10",
+             "Preset a: climbable slope 50 is past the inverted slope 10"),
+        ] {
+            assert_eq!(parse(text, &mut builtin()), Err(err.into()));
+        }
+    }
+
+    #[test]
+    fn adjust_keeps_thresholds_in_order() {
+        let mut slopes = Slopes::new(None).unwrap();
+        slopes.adjust(100., 0.);
+        assert_eq!((slopes.preset().climbable, slopes.preset().inverted),
+                   (90., 90.));
+
+        slopes.adjust(0., -30.);
+        assert_eq!((slopes.preset().climbable, slopes.preset().inverted),
+                   (60., 60.));
+
+        slopes.adjust(-100., 200.);
+        assert_eq!((slopes.preset().climbable, slopes.preset().inverted),
+                   (0., 180.));
+    }
+}